@@ -0,0 +1,370 @@
+use crate::{
+    auth::{self, AuthErrorCode},
+    http::{
+        HttpResponse, StreamingCallbackHttpResponse, StreamingCallbackToken, StreamingStrategy,
+    },
+    ASSETS, ASSET_CHUNKS, NEXT_ASSET_ID,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use candid::{CandidType, Decode, Deserialize, Encode, Func, Principal};
+use ic_certified_map::{labeled, labeled_hash, AsHashTree, Hash, RbTree};
+use ic_stable_structures::{storable::Bound, Storable};
+use serde::Serialize;
+use serde_bytes::ByteBuf;
+use sha2::{Digest, Sha256};
+use std::{borrow::Cow, cell::RefCell};
+
+pub type AssetId = u64;
+
+/// Assets are served at this path followed by their id.
+pub const ASSET_PATH_PREFIX: &str = "/assets/";
+
+/// Largest chunk accepted by `append_asset_chunk`, leaving room in the 2 MiB ingress limit.
+pub const MAX_CHUNK_SIZE: usize = 1_900_000;
+
+const ASSET_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+thread_local! {
+    // Hashes of committed assets by path, rebuilt from `ASSETS` after an upgrade
+    static ASSET_HASHES: RefCell<RbTree<String, Hash>> = RefCell::new(RbTree::new());
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct Asset {
+    pub content_type: String,
+    pub chunk_count: u32,
+    pub length: u64,
+    pub status: AssetStatus,
+    pub uploader: Principal,
+    pub created_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum AssetStatus {
+    Uploading,
+    Committed { sha256: ByteBuf },
+}
+
+impl Storable for Asset {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AssetChunkKey {
+    pub asset_id: AssetId,
+    pub index: u32,
+}
+
+impl Storable for AssetChunkKey {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum AssetErrorCode {
+    Unauthorized,
+    AssetNotFound,
+    InvalidContentType,
+    InvalidChunk,
+    AlreadyCommitted,
+    HashMismatch,
+}
+
+impl From<AuthErrorCode> for AssetErrorCode {
+    fn from(code: AuthErrorCode) -> Self {
+        match code {
+            AuthErrorCode::Unauthorized => AssetErrorCode::Unauthorized,
+        }
+    }
+}
+
+pub fn asset_path(asset_id: AssetId) -> String {
+    format!("{}{}", ASSET_PATH_PREFIX, asset_id)
+}
+
+fn get_asset(asset_id: AssetId) -> Result<Asset, (AssetErrorCode, String)> {
+    ASSETS.with_borrow(|p| p.get(&asset_id)).ok_or((
+        AssetErrorCode::AssetNotFound,
+        format!("Asset with id {} not found in the store", asset_id),
+    ))
+}
+
+/// Starts an upload and returns the id the chunks are appended to.
+///
+/// Ids of deleted assets are never handed out again: their paths are cached as immutable,
+/// so reusing one would keep serving the old bytes.
+pub(crate) fn begin_asset_upload(
+    caller: Principal,
+    content_type: String,
+) -> Result<AssetId, (AssetErrorCode, String)> {
+    let uploader = auth::require_staff::<AssetErrorCode>(caller)?;
+
+    if !content_type.starts_with("image/") {
+        return Err((
+            AssetErrorCode::InvalidContentType,
+            format!("Content type {} is not an image type", content_type),
+        ));
+    }
+
+    let asset = Asset {
+        content_type,
+        chunk_count: 0,
+        length: 0,
+        status: AssetStatus::Uploading,
+        uploader,
+        created_at: ic_cdk::api::time(),
+    };
+
+    let asset_id = ASSETS.with_borrow_mut(|p| {
+        // Assets restored from a backup may lie beyond the counter
+        let asset_id = NEXT_ASSET_ID
+            .with_borrow(|c| *c.get())
+            .max(p.last_key_value().map_or(0, |(id, _)| id + 1));
+        p.insert(asset_id, asset);
+        asset_id
+    });
+    let _ = NEXT_ASSET_ID.with_borrow_mut(|c| c.set(asset_id + 1));
+
+    Ok(asset_id)
+}
+
+/// Stores a chunk of an upload.
+///
+/// Chunks are appended in order. Sending an index that was already stored replaces
+/// that chunk, so a failed call can be retried.
+pub(crate) fn append_asset_chunk(
+    caller: Principal,
+    asset_id: AssetId,
+    index: u32,
+    bytes: ByteBuf,
+) -> Result<(), (AssetErrorCode, String)> {
+    auth::require_staff::<AssetErrorCode>(caller)?;
+
+    let mut asset = get_asset(asset_id)?;
+    if asset.status != AssetStatus::Uploading {
+        return Err((
+            AssetErrorCode::AlreadyCommitted,
+            format!("Asset with id {} is already committed", asset_id),
+        ));
+    }
+    if index > asset.chunk_count || bytes.is_empty() || bytes.len() > MAX_CHUNK_SIZE {
+        return Err((
+            AssetErrorCode::InvalidChunk,
+            format!(
+                "Chunk {} of asset with id {} must follow chunk {} and hold 1 to {} bytes",
+                index, asset_id, asset.chunk_count, MAX_CHUNK_SIZE
+            ),
+        ));
+    }
+
+    let key = AssetChunkKey { asset_id, index };
+    let prev = ASSET_CHUNKS.with_borrow_mut(|p| p.insert(key, bytes.into_vec()));
+
+    if prev.is_none() {
+        asset.chunk_count += 1;
+        ASSETS.with_borrow_mut(|p| p.insert(asset_id, asset));
+    }
+
+    Ok(())
+}
+
+/// Verifies the SHA-256 of the uploaded bytes, then makes the asset available over HTTP.
+/// Returns the path the asset is served at.
+pub(crate) fn commit_asset_upload(
+    caller: Principal,
+    asset_id: AssetId,
+    sha256: ByteBuf,
+) -> Result<String, (AssetErrorCode, String)> {
+    auth::require_staff::<AssetErrorCode>(caller)?;
+
+    let mut asset = get_asset(asset_id)?;
+    if asset.status != AssetStatus::Uploading {
+        return Err((
+            AssetErrorCode::AlreadyCommitted,
+            format!("Asset with id {} is already committed", asset_id),
+        ));
+    }
+
+    let mut hasher = Sha256::new();
+    let mut length = 0;
+    ASSET_CHUNKS.with_borrow(|p| {
+        for index in 0..asset.chunk_count {
+            if let Some(chunk) = p.get(&AssetChunkKey { asset_id, index }) {
+                length += chunk.len() as u64;
+                hasher.update(&chunk);
+            }
+        }
+    });
+    let hash: Hash = hasher.finalize().into();
+
+    if hash.as_slice() != sha256.as_slice() {
+        return Err((
+            AssetErrorCode::HashMismatch,
+            format!("SHA-256 of asset with id {} does not match", asset_id),
+        ));
+    }
+
+    asset.length = length;
+    asset.status = AssetStatus::Committed { sha256 };
+    ASSETS.with_borrow_mut(|p| p.insert(asset_id, asset));
+
+    let path = asset_path(asset_id);
+    ASSET_HASHES.with_borrow_mut(|tree| tree.insert(path.clone(), hash));
+    update_certified_data();
+
+    Ok(path)
+}
+
+/// Deletes an asset and its chunks, whether or not it was committed.
+pub(crate) fn delete_asset(
+    caller: Principal,
+    asset_id: AssetId,
+) -> Result<(), (AssetErrorCode, String)> {
+    auth::require_staff::<AssetErrorCode>(caller)?;
+
+    let asset = get_asset(asset_id)?;
+
+    ASSET_CHUNKS.with_borrow_mut(|p| {
+        for index in 0..asset.chunk_count {
+            p.remove(&AssetChunkKey { asset_id, index });
+        }
+    });
+    ASSETS.with_borrow_mut(|p| p.remove(&asset_id));
+
+    ASSET_HASHES.with_borrow_mut(|tree| tree.delete(asset_path(asset_id).as_bytes()));
+    update_certified_data();
+
+    Ok(())
+}
+
+pub(crate) fn get_asset_info(asset_id: AssetId) -> Option<Asset> {
+    ASSETS.with_borrow(|p| p.get(&asset_id))
+}
+
+fn update_certified_data() {
+    let root_hash =
+        ASSET_HASHES.with_borrow(|tree| labeled_hash(b"http_assets", &tree.root_hash()));
+    ic_cdk::api::set_certified_data(&root_hash);
+}
+
+/// Rebuilds the certified hashes of committed assets, which are kept on the heap.
+pub(crate) fn rebuild_certified_assets() {
+    ASSET_HASHES.with_borrow_mut(|tree| {
+        *tree = RbTree::new();
+        ASSETS.with_borrow(|p| {
+            for (asset_id, asset) in p.iter() {
+                if let AssetStatus::Committed { sha256 } = asset.status {
+                    if let Ok(hash) = Hash::try_from(sha256.as_slice()) {
+                        tree.insert(asset_path(asset_id), hash);
+                    }
+                }
+            }
+        });
+    });
+    update_certified_data();
+}
+
+fn certificate_header(path: &str) -> Option<(String, String)> {
+    let certificate = ic_cdk::api::data_certificate()?;
+
+    let tree = ASSET_HASHES.with_borrow(|tree| {
+        let tree = labeled(b"http_assets", tree.witness(path.as_bytes()));
+
+        let mut serializer = serde_cbor::ser::Serializer::new(vec![]);
+        serializer.self_describe().ok()?;
+        tree.serialize(&mut serializer).ok()?;
+        Some(serializer.into_inner())
+    })?;
+
+    Some((
+        "IC-Certificate".to_string(),
+        format!(
+            "certificate=:{}:, tree=:{}:",
+            BASE64.encode(certificate),
+            BASE64.encode(tree)
+        ),
+    ))
+}
+
+pub(crate) fn serve_asset(asset_id: AssetId) -> HttpResponse {
+    let asset = match ASSETS.with_borrow(|p| p.get(&asset_id)) {
+        Some(asset) if asset.status != AssetStatus::Uploading => asset,
+        _ => return HttpResponse::error(404, "Not found"),
+    };
+
+    let body = ASSET_CHUNKS
+        .with_borrow(|p| p.get(&AssetChunkKey { asset_id, index: 0 }))
+        .unwrap_or_default();
+
+    let mut headers = vec![
+        ("Content-Type".to_string(), asset.content_type.clone()),
+        ("Content-Length".to_string(), asset.length.to_string()),
+        ("Cache-Control".to_string(), ASSET_CACHE_CONTROL.to_string()),
+    ];
+    if let Some(header) = certificate_header(&asset_path(asset_id)) {
+        headers.push(header);
+    }
+
+    let mut res = HttpResponse::new(200, headers, body);
+    if asset.chunk_count > 1 {
+        res.streaming_strategy = Some(StreamingStrategy::Callback {
+            callback: Func {
+                principal: ic_cdk::id(),
+                method: "http_request_streaming_callback".to_string(),
+            },
+            token: StreamingCallbackToken {
+                asset_id,
+                chunk_index: 1,
+            },
+        });
+    }
+
+    res
+}
+
+pub(crate) fn http_request_streaming_callback(
+    token: StreamingCallbackToken,
+) -> StreamingCallbackHttpResponse {
+    let StreamingCallbackToken {
+        asset_id,
+        chunk_index,
+    } = token;
+
+    let chunk_count = ASSETS
+        .with_borrow(|p| p.get(&asset_id))
+        .map_or(0, |asset| asset.chunk_count);
+
+    let body = ASSET_CHUNKS
+        .with_borrow(|p| {
+            p.get(&AssetChunkKey {
+                asset_id,
+                index: chunk_index,
+            })
+        })
+        .unwrap_or_default();
+
+    let token = (chunk_index + 1 < chunk_count).then_some(StreamingCallbackToken {
+        asset_id,
+        chunk_index: chunk_index + 1,
+    });
+
+    StreamingCallbackHttpResponse {
+        body: ByteBuf::from(body),
+        token,
+    }
+}
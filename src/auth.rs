@@ -0,0 +1,98 @@
+use crate::STAFF;
+use candid::{CandidType, Deserialize, Principal};
+
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum AuthErrorCode {
+    Unauthorized,
+}
+
+/// Resolves the principal a call is made by.
+///
+/// Controllers (such as the platform canister) may act on behalf of the `caller` they pass;
+/// anyone else always acts as themselves.
+pub(crate) fn effective_caller(caller: Principal) -> Principal {
+    let direct = ic_cdk::caller();
+    if ic_cdk::api::is_controller(&direct) {
+        caller
+    } else {
+        direct
+    }
+}
+
+pub(crate) fn is_owner(principal: &Principal) -> bool {
+    ic_cdk::api::is_controller(principal)
+}
+
+pub(crate) fn is_staff(principal: &Principal) -> bool {
+    is_owner(principal) || STAFF.with_borrow(|p| p.contains_key(principal))
+}
+
+/// Checks that the call is made by a store owner and returns the owner's principal.
+///
+/// The error code is converted so that each module can report it with its own error type.
+pub(crate) fn require_owner<C: From<AuthErrorCode>>(
+    caller: Principal,
+) -> Result<Principal, (C, String)> {
+    let principal = effective_caller(caller);
+    if is_owner(&principal) {
+        Ok(principal)
+    } else {
+        Err((
+            AuthErrorCode::Unauthorized.into(),
+            format!("{} is not an owner of the store", principal),
+        ))
+    }
+}
+
+/// Checks that the call is made by store staff or an owner and returns their principal.
+pub(crate) fn require_staff<C: From<AuthErrorCode>>(
+    caller: Principal,
+) -> Result<Principal, (C, String)> {
+    let principal = effective_caller(caller);
+    if is_staff(&principal) {
+        Ok(principal)
+    } else {
+        Err((
+            AuthErrorCode::Unauthorized.into(),
+            format!("{} is not a staff member of the store", principal),
+        ))
+    }
+}
+
+/// Checks that the call is made by a signed-in principal rather than the anonymous one,
+/// and returns that principal.
+pub(crate) fn require_authenticated<C: From<AuthErrorCode>>(
+    caller: Principal,
+) -> Result<Principal, (C, String)> {
+    let principal = effective_caller(caller);
+    if principal != Principal::anonymous() {
+        Ok(principal)
+    } else {
+        Err((
+            AuthErrorCode::Unauthorized.into(),
+            "Anonymous principals cannot do this".to_string(),
+        ))
+    }
+}
+
+pub(crate) fn add_staff(
+    caller: Principal,
+    staff: Principal,
+) -> Result<(), (AuthErrorCode, String)> {
+    require_owner::<AuthErrorCode>(caller)?;
+    STAFF.with_borrow_mut(|p| p.insert(staff, ic_cdk::api::time()));
+    Ok(())
+}
+
+pub(crate) fn remove_staff(
+    caller: Principal,
+    staff: Principal,
+) -> Result<(), (AuthErrorCode, String)> {
+    require_owner::<AuthErrorCode>(caller)?;
+    STAFF.with_borrow_mut(|p| p.remove(&staff));
+    Ok(())
+}
+
+pub(crate) fn list_staff() -> Vec<Principal> {
+    STAFF.with_borrow(|p| p.iter().map(|(principal, _)| principal).collect())
+}
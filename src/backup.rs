@@ -0,0 +1,585 @@
+use crate::{
+    asset::{self, Asset, AssetChunkKey, AssetId},
+    auth::{self, AuthErrorCode},
+    data::StoreData,
+    item::spec::SpecCategory,
+    item::{
+        filter,
+        question::{QaVoteKey, Question, QuestionKey},
+        relation::{self, ItemRelationKey, ItemRelations},
+        review::{self, Review, ReviewKey},
+        revision::{ItemRevision, ItemRevisionKey},
+        Item, ItemId, ItemKey,
+    },
+    log::LogEntry,
+    media::{MediaAsset, MediaId},
+    spec_template::SpecTemplateId,
+    wishlist::{Wishlist, WishlistKey},
+    ASSETS, ASSET_CHUNKS, ITEMS, ITEMS_IN_ID, ITEM_RATINGS, ITEM_RELATIONS, ITEM_REVISIONS, LOG,
+    MEDIA_LIBRARY, QA_VOTES, QUESTIONS, RESTORE_STATE, REVIEWS, SPEC_TEMPLATES, SPEC_VALUE_INDEX,
+    STAFF, STORE_DATA, WISHLISTS,
+};
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{storable::Bound, Storable};
+use std::borrow::Cow;
+
+/// Version of the backup envelope. Restores only accept chunks of this version.
+pub const BACKUP_VERSION: u32 = 2;
+
+/// Largest encoded size of the entries of a chunk, so that a chunk fits in
+/// both a query response and the ingress message of a restore.
+pub const MAX_BACKUP_CHUNK_SIZE: usize = 1_800_000;
+
+/// Position in a backup. Sections are exported in the order of the variants.
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum BackupCursor {
+    StoreData,
+    Staff { after: Option<Principal> },
+    Assets { after: Option<AssetId> },
+    AssetChunks { after: Option<AssetChunkKey> },
+    MediaLibrary { after: Option<MediaId> },
+    SpecTemplates { after: Option<SpecTemplateId> },
+    Items { after: Option<ItemKey> },
+    ItemsInId { after: Option<ItemId> },
+    ItemRevisions { after: Option<ItemRevisionKey> },
+    ItemRelations { after: Option<ItemRelationKey> },
+    Reviews { after: Option<ReviewKey> },
+    Questions { after: Option<QuestionKey> },
+    QaVotes { after: Option<QaVoteKey> },
+    Wishlists { after: Option<WishlistKey> },
+    Log { index: u64 },
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub enum BackupEntries {
+    StoreData(StoreData),
+    Staff(Vec<(Principal, u64)>),
+    Assets(Vec<(AssetId, Asset)>),
+    AssetChunks(Vec<(AssetChunkKey, Vec<u8>)>),
+    MediaLibrary(Vec<(MediaId, MediaAsset)>),
+    SpecTemplates(Vec<(SpecTemplateId, SpecCategory)>),
+    Items(Vec<(ItemKey, Item)>),
+    ItemsInId(Vec<(ItemId, ItemKey)>),
+    ItemRevisions(Vec<(ItemRevisionKey, ItemRevision)>),
+    ItemRelations(Vec<(ItemRelationKey, ItemRelations)>),
+    Reviews(Vec<(ReviewKey, Review)>),
+    Questions(Vec<(QuestionKey, Question)>),
+    QaVotes(Vec<(QaVoteKey, u64)>),
+    Wishlists(Vec<(WishlistKey, Wishlist)>),
+    Log(Vec<LogEntry>),
+}
+
+/// A chunk of a backup, restored in the same order it was exported.
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct BackupChunk {
+    pub version: u32,
+    pub cursor: BackupCursor,
+    pub entries: BackupEntries,
+    pub include_log: bool,
+    // `None` on the last chunk
+    pub next: Option<BackupCursor>,
+}
+
+/// Progress of a restore, kept in stable memory so that a restore survives an upgrade.
+#[derive(CandidType, Deserialize, Debug, Clone, Default)]
+pub struct RestoreState {
+    // Cursor of the chunk expected next, `None` when no restore is in progress
+    pub expected: Option<BackupCursor>,
+    // Cursor of the chunk restored last, so that it can be sent again
+    pub last: Option<BackupCursor>,
+}
+
+impl Storable for RestoreState {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct BackupRequest {
+    // `None` to start a new backup, otherwise the `next` of the previous chunk
+    pub cursor: Option<BackupCursor>,
+    pub include_log: bool,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum BackupErrorCode {
+    Unauthorized,
+    UnsupportedVersion,
+    RestoreNotStarted,
+    UnexpectedChunk,
+    InvalidBackup,
+}
+
+impl From<AuthErrorCode> for BackupErrorCode {
+    fn from(code: AuthErrorCode) -> Self {
+        match code {
+            AuthErrorCode::Unauthorized => BackupErrorCode::Unauthorized,
+        }
+    }
+}
+
+/// Collects entries until the chunk would grow past `MAX_BACKUP_CHUNK_SIZE`.
+/// The first entry is always taken, so every chunk makes progress.
+fn take_sized<K, V: Storable>(iter: impl Iterator<Item = (K, V)>) -> (Vec<(K, V)>, bool) {
+    let mut size = 0;
+    let mut entries = Vec::new();
+
+    for (key, value) in iter {
+        size += value.to_bytes().len();
+        if size > MAX_BACKUP_CHUNK_SIZE && !entries.is_empty() {
+            return (entries, true);
+        }
+        entries.push((key, value));
+    }
+
+    (entries, false)
+}
+
+fn section_after(cursor: &BackupCursor, include_log: bool) -> Option<BackupCursor> {
+    match cursor {
+        BackupCursor::StoreData => Some(BackupCursor::Staff { after: None }),
+        BackupCursor::Staff { .. } => Some(BackupCursor::Assets { after: None }),
+        BackupCursor::Assets { .. } => Some(BackupCursor::AssetChunks { after: None }),
+        BackupCursor::AssetChunks { .. } => Some(BackupCursor::MediaLibrary { after: None }),
+        BackupCursor::MediaLibrary { .. } => Some(BackupCursor::SpecTemplates { after: None }),
+        BackupCursor::SpecTemplates { .. } => Some(BackupCursor::Items { after: None }),
+        BackupCursor::Items { .. } => Some(BackupCursor::ItemsInId { after: None }),
+        BackupCursor::ItemsInId { .. } => Some(BackupCursor::ItemRevisions { after: None }),
+        BackupCursor::ItemRevisions { .. } => Some(BackupCursor::ItemRelations { after: None }),
+        BackupCursor::ItemRelations { .. } => Some(BackupCursor::Reviews { after: None }),
+        BackupCursor::Reviews { .. } => Some(BackupCursor::Questions { after: None }),
+        BackupCursor::Questions { .. } => Some(BackupCursor::QaVotes { after: None }),
+        BackupCursor::QaVotes { .. } => Some(BackupCursor::Wishlists { after: None }),
+        BackupCursor::Wishlists { .. } => include_log.then_some(BackupCursor::Log { index: 0 }),
+        BackupCursor::Log { .. } => None,
+    }
+}
+
+/// Exports the chunk of the backup that starts at `arg.cursor`.
+pub(crate) fn export_backup(
+    caller: Principal,
+    arg: BackupRequest,
+) -> Result<BackupChunk, (BackupErrorCode, String)> {
+    auth::require_owner::<BackupErrorCode>(caller)?;
+
+    let cursor = arg.cursor.unwrap_or(BackupCursor::StoreData);
+
+    let (entries, next) = match &cursor {
+        BackupCursor::StoreData => {
+            let store_data = STORE_DATA.with_borrow(|p| p.get().clone());
+            (BackupEntries::StoreData(store_data), None)
+        }
+        BackupCursor::Staff { after } => {
+            let (entries, more) = STAFF.with_borrow(|p| match after {
+                Some(after) => take_sized(p.range(*after..).skip_while(|(k, _)| k == after)),
+                None => take_sized(p.iter()),
+            });
+            let next = more.then(|| BackupCursor::Staff {
+                after: entries.last().map(|(k, _)| *k),
+            });
+            (BackupEntries::Staff(entries), next)
+        }
+        BackupCursor::Assets { after } => {
+            let (entries, more) = ASSETS.with_borrow(|p| match after {
+                Some(after) => take_sized(p.range(*after..).skip_while(|(k, _)| k == after)),
+                None => take_sized(p.iter()),
+            });
+            let next = more.then(|| BackupCursor::Assets {
+                after: entries.last().map(|(k, _)| *k),
+            });
+            (BackupEntries::Assets(entries), next)
+        }
+        BackupCursor::AssetChunks { after } => {
+            let (entries, more) = ASSET_CHUNKS.with_borrow(|p| match after {
+                Some(after) => take_sized(p.range(after.clone()..).skip_while(|(k, _)| k == after)),
+                None => take_sized(p.iter()),
+            });
+            let next = more.then(|| BackupCursor::AssetChunks {
+                after: entries.last().map(|(k, _)| k.clone()),
+            });
+            (BackupEntries::AssetChunks(entries), next)
+        }
+        BackupCursor::MediaLibrary { after } => {
+            let (entries, more) = MEDIA_LIBRARY.with_borrow(|p| match after {
+                Some(after) => take_sized(p.range(*after..).skip_while(|(k, _)| k == after)),
+                None => take_sized(p.iter()),
+            });
+            let next = more.then(|| BackupCursor::MediaLibrary {
+                after: entries.last().map(|(k, _)| *k),
+            });
+            (BackupEntries::MediaLibrary(entries), next)
+        }
+        BackupCursor::SpecTemplates { after } => {
+            let (entries, more) = SPEC_TEMPLATES.with_borrow(|p| match after {
+                Some(after) => take_sized(p.range(*after..).skip_while(|(k, _)| k == after)),
+                None => take_sized(p.iter()),
+            });
+            let next = more.then(|| BackupCursor::SpecTemplates {
+                after: entries.last().map(|(k, _)| *k),
+            });
+            (BackupEntries::SpecTemplates(entries), next)
+        }
+        BackupCursor::Items { after } => {
+            let (entries, more) = ITEMS.with_borrow(|p| match after {
+                Some(after) => take_sized(p.range(*after..).skip_while(|(k, _)| k == after)),
+                None => take_sized(p.iter()),
+            });
+            let next = more.then(|| BackupCursor::Items {
+                after: entries.last().map(|(k, _)| *k),
+            });
+            (BackupEntries::Items(entries), next)
+        }
+        BackupCursor::ItemsInId { after } => {
+            let (entries, more) = ITEMS_IN_ID.with_borrow(|p| match after {
+                Some(after) => take_sized(p.range(*after..).skip_while(|(k, _)| k == after)),
+                None => take_sized(p.iter()),
+            });
+            let next = more.then(|| BackupCursor::ItemsInId {
+                after: entries.last().map(|(k, _)| *k),
+            });
+            (BackupEntries::ItemsInId(entries), next)
+        }
+        BackupCursor::ItemRevisions { after } => {
+            let (entries, more) = ITEM_REVISIONS.with_borrow(|p| match after {
+                Some(after) => take_sized(p.range(after.clone()..).skip_while(|(k, _)| k == after)),
+                None => take_sized(p.iter()),
+            });
+            let next = more.then(|| BackupCursor::ItemRevisions {
+                after: entries.last().map(|(k, _)| k.clone()),
+            });
+            (BackupEntries::ItemRevisions(entries), next)
+        }
+        BackupCursor::ItemRelations { after } => {
+            let (entries, more) = ITEM_RELATIONS.with_borrow(|p| match after {
+                Some(after) => take_sized(p.range(after.clone()..).skip_while(|(k, _)| k == after)),
+                None => take_sized(p.iter()),
+            });
+            let next = more.then(|| BackupCursor::ItemRelations {
+                after: entries.last().map(|(k, _)| k.clone()),
+            });
+            (BackupEntries::ItemRelations(entries), next)
+        }
+        BackupCursor::Reviews { after } => {
+            let (entries, more) = REVIEWS.with_borrow(|p| match after {
+                Some(after) => take_sized(p.range(after.clone()..).skip_while(|(k, _)| k == after)),
+                None => take_sized(p.iter()),
+            });
+            let next = more.then(|| BackupCursor::Reviews {
+                after: entries.last().map(|(k, _)| k.clone()),
+            });
+            (BackupEntries::Reviews(entries), next)
+        }
+        BackupCursor::Questions { after } => {
+            let (entries, more) = QUESTIONS.with_borrow(|p| match after {
+                Some(after) => take_sized(p.range(after.clone()..).skip_while(|(k, _)| k == after)),
+                None => take_sized(p.iter()),
+            });
+            let next = more.then(|| BackupCursor::Questions {
+                after: entries.last().map(|(k, _)| k.clone()),
+            });
+            (BackupEntries::Questions(entries), next)
+        }
+        BackupCursor::QaVotes { after } => {
+            let (entries, more) = QA_VOTES.with_borrow(|p| match after {
+                Some(after) => take_sized(p.range(after.clone()..).skip_while(|(k, _)| k == after)),
+                None => take_sized(p.iter()),
+            });
+            let next = more.then(|| BackupCursor::QaVotes {
+                after: entries.last().map(|(k, _)| k.clone()),
+            });
+            (BackupEntries::QaVotes(entries), next)
+        }
+        BackupCursor::Wishlists { after } => {
+            let (entries, more) = WISHLISTS.with_borrow(|p| match after {
+                Some(after) => take_sized(p.range(after.clone()..).skip_while(|(k, _)| k == after)),
+                None => take_sized(p.iter()),
+            });
+            let next = more.then(|| BackupCursor::Wishlists {
+                after: entries.last().map(|(k, _)| k.clone()),
+            });
+            (BackupEntries::Wishlists(entries), next)
+        }
+        BackupCursor::Log { index } => {
+            let (entries, more) = LOG.with_borrow(|log| {
+                take_sized((*index..log.len()).filter_map(|i| Some((i, log.get(i)?))))
+            });
+            let next = more.then(|| BackupCursor::Log {
+                index: index + entries.len() as u64,
+            });
+            let entries = entries.into_iter().map(|(_, entry)| entry).collect();
+            (BackupEntries::Log(entries), next)
+        }
+    };
+
+    let next = next.or_else(|| section_after(&cursor, arg.include_log));
+
+    Ok(BackupChunk {
+        version: BACKUP_VERSION,
+        cursor,
+        entries,
+        include_log: arg.include_log,
+        next,
+    })
+}
+
+/// Clears everything a backup holds and waits for its chunks: the store data, staff,
+/// assets, media library, spec templates, items with their revisions and relations,
+/// reviews, questions and wishlists. Indexes and rating summaries are cleared too,
+/// and rebuilt as the chunks are restored.
+///
+/// The log can only be appended to, so restored log entries follow the existing ones.
+pub(crate) fn begin_restore(caller: Principal) -> Result<(), (BackupErrorCode, String)> {
+    auth::require_owner::<BackupErrorCode>(caller)?;
+
+    STORE_DATA
+        .with_borrow_mut(|p| p.set(StoreData::None))
+        .map_err(|e| {
+            (
+                BackupErrorCode::InvalidBackup,
+                format!("Failed to clear the store data: {:?}", e),
+            )
+        })?;
+    STAFF.with_borrow_mut(|p| p.clear_new());
+    ASSETS.with_borrow_mut(|p| p.clear_new());
+    ASSET_CHUNKS.with_borrow_mut(|p| p.clear_new());
+    MEDIA_LIBRARY.with_borrow_mut(|p| p.clear_new());
+    SPEC_TEMPLATES.with_borrow_mut(|p| p.clear_new());
+    ITEMS.with_borrow_mut(|p| p.clear_new());
+    ITEMS_IN_ID.with_borrow_mut(|p| p.clear_new());
+    ITEM_REVISIONS.with_borrow_mut(|p| p.clear_new());
+    ITEM_RELATIONS.with_borrow_mut(|p| p.clear_new());
+    REVIEWS.with_borrow_mut(|p| p.clear_new());
+    ITEM_RATINGS.with_borrow_mut(|p| p.clear_new());
+    QUESTIONS.with_borrow_mut(|p| p.clear_new());
+    QA_VOTES.with_borrow_mut(|p| p.clear_new());
+    WISHLISTS.with_borrow_mut(|p| p.clear_new());
+    SPEC_VALUE_INDEX.with_borrow_mut(|p| p.clear_new());
+    asset::rebuild_certified_assets();
+
+    set_restore_state(RestoreState {
+        expected: Some(BackupCursor::StoreData),
+        last: None,
+    })
+}
+
+fn set_restore_state(state: RestoreState) -> Result<(), (BackupErrorCode, String)> {
+    RESTORE_STATE
+        .with_borrow_mut(|p| p.set(state))
+        .map(|_| ())
+        .map_err(|e| {
+            (
+                BackupErrorCode::InvalidBackup,
+                format!("Failed to save the restore progress: {:?}", e),
+            )
+        })
+}
+
+/// Restores a chunk and returns the cursor of the chunk expected next,
+/// or `None` once the backup is fully restored.
+///
+/// Sending the chunk that was restored last again is accepted and has no further effect,
+/// even once the restore is complete, so a call that failed on the way back can be retried.
+pub(crate) fn restore_backup_chunk(
+    caller: Principal,
+    chunk: BackupChunk,
+) -> Result<Option<BackupCursor>, (BackupErrorCode, String)> {
+    auth::require_owner::<BackupErrorCode>(caller)?;
+
+    if chunk.version != BACKUP_VERSION {
+        return Err((
+            BackupErrorCode::UnsupportedVersion,
+            format!(
+                "Backup version {} is not supported, expected {}",
+                chunk.version, BACKUP_VERSION
+            ),
+        ));
+    }
+
+    let state = RESTORE_STATE.with_borrow(|p| p.get().clone());
+    if state.last.as_ref() == Some(&chunk.cursor) && chunk.next == state.expected {
+        return Ok(state.expected);
+    }
+    let expected = state.expected.ok_or((
+        BackupErrorCode::RestoreNotStarted,
+        "No restore is in progress".to_string(),
+    ))?;
+    if chunk.cursor != expected {
+        return Err((
+            BackupErrorCode::UnexpectedChunk,
+            format!(
+                "Expected the chunk at {:?}, got {:?}",
+                expected, chunk.cursor
+            ),
+        ));
+    }
+
+    validate_chunk(&chunk)?;
+
+    // Relations are restored after the items, so links to items missing
+    // from the backup are dropped once the last chunk of relations is in
+    let relations_done = matches!(chunk.cursor, BackupCursor::ItemRelations { .. })
+        && !matches!(chunk.next, Some(BackupCursor::ItemRelations { .. }));
+    // Assets are certified once all of their chunks are in
+    let assets_done = matches!(chunk.cursor, BackupCursor::AssetChunks { .. })
+        && !matches!(chunk.next, Some(BackupCursor::AssetChunks { .. }));
+
+    match chunk.entries {
+        BackupEntries::StoreData(store_data) => {
+            STORE_DATA
+                .with_borrow_mut(|p| p.set(store_data))
+                .map_err(|e| {
+                    (
+                        BackupErrorCode::InvalidBackup,
+                        format!("Failed to restore the store data: {:?}", e),
+                    )
+                })?;
+        }
+        BackupEntries::Staff(entries) => STAFF.with_borrow_mut(|p| {
+            entries.into_iter().for_each(|(k, v)| {
+                p.insert(k, v);
+            })
+        }),
+        BackupEntries::Assets(entries) => ASSETS.with_borrow_mut(|p| {
+            entries.into_iter().for_each(|(k, v)| {
+                p.insert(k, v);
+            })
+        }),
+        BackupEntries::AssetChunks(entries) => ASSET_CHUNKS.with_borrow_mut(|p| {
+            entries.into_iter().for_each(|(k, v)| {
+                p.insert(k, v);
+            })
+        }),
+        BackupEntries::MediaLibrary(entries) => MEDIA_LIBRARY.with_borrow_mut(|p| {
+            entries.into_iter().for_each(|(k, v)| {
+                p.insert(k, v);
+            })
+        }),
+        BackupEntries::SpecTemplates(entries) => SPEC_TEMPLATES.with_borrow_mut(|p| {
+            entries.into_iter().for_each(|(k, v)| {
+                p.insert(k, v);
+            })
+        }),
+        BackupEntries::Items(entries) => ITEMS.with_borrow_mut(|p| {
+            entries.into_iter().for_each(|(k, v)| {
+                p.insert(k, v);
+            })
+        }),
+        BackupEntries::ItemsInId(entries) => {
+            // The index is not part of the backup; it is rebuilt from the live items
+            // once they are restored
+            entries.iter().for_each(|(_, key)| {
+                if let Some(item) = ITEMS.with_borrow(|p| p.get(key)) {
+                    filter::index_item(&item);
+                }
+            });
+            ITEMS_IN_ID.with_borrow_mut(|p| {
+                entries.into_iter().for_each(|(k, v)| {
+                    p.insert(k, v);
+                })
+            })
+        }
+        BackupEntries::ItemRevisions(entries) => ITEM_REVISIONS.with_borrow_mut(|p| {
+            entries.into_iter().for_each(|(k, v)| {
+                p.insert(k, v);
+            })
+        }),
+        BackupEntries::ItemRelations(entries) => ITEM_RELATIONS.with_borrow_mut(|p| {
+            entries.into_iter().for_each(|(k, v)| {
+                p.insert(k, v);
+            })
+        }),
+        BackupEntries::Reviews(entries) => {
+            // Rating summaries are not part of the backup and are recomputed instead
+            let mut item_ids: Vec<ItemId> = entries.iter().map(|(k, _)| k.item_id).collect();
+            item_ids.dedup();
+            REVIEWS.with_borrow_mut(|p| {
+                entries.into_iter().for_each(|(k, v)| {
+                    p.insert(k, v);
+                })
+            });
+            item_ids.into_iter().for_each(review::update_rating_summary);
+        }
+        BackupEntries::Questions(entries) => QUESTIONS.with_borrow_mut(|p| {
+            entries.into_iter().for_each(|(k, v)| {
+                p.insert(k, v);
+            })
+        }),
+        BackupEntries::QaVotes(entries) => QA_VOTES.with_borrow_mut(|p| {
+            entries.into_iter().for_each(|(k, v)| {
+                p.insert(k, v);
+            })
+        }),
+        BackupEntries::Wishlists(entries) => WISHLISTS.with_borrow_mut(|p| {
+            entries.into_iter().for_each(|(k, v)| {
+                p.insert(k, v);
+            })
+        }),
+        BackupEntries::Log(entries) => LOG.with_borrow_mut(|log| {
+            entries.iter().for_each(|entry| {
+                let _ = log.append(entry);
+            })
+        }),
+    }
+
+    if relations_done {
+        relation::prune_dead_links();
+    }
+    if assets_done {
+        asset::rebuild_certified_assets();
+    }
+    set_restore_state(RestoreState {
+        expected: chunk.next.clone(),
+        last: Some(chunk.cursor),
+    })?;
+
+    Ok(chunk.next)
+}
+
+/// Checks that the entries belong to the section of the cursor, and that every item id
+/// points at an item with that id, which the items section has restored by then.
+fn validate_chunk(chunk: &BackupChunk) -> Result<(), (BackupErrorCode, String)> {
+    let invalid = |message: String| Err((BackupErrorCode::InvalidBackup, message));
+
+    match (&chunk.cursor, &chunk.entries) {
+        (BackupCursor::StoreData, BackupEntries::StoreData(_))
+        | (BackupCursor::Staff { .. }, BackupEntries::Staff(_))
+        | (BackupCursor::Assets { .. }, BackupEntries::Assets(_))
+        | (BackupCursor::AssetChunks { .. }, BackupEntries::AssetChunks(_))
+        | (BackupCursor::MediaLibrary { .. }, BackupEntries::MediaLibrary(_))
+        | (BackupCursor::SpecTemplates { .. }, BackupEntries::SpecTemplates(_))
+        | (BackupCursor::Items { .. }, BackupEntries::Items(_))
+        | (BackupCursor::ItemRevisions { .. }, BackupEntries::ItemRevisions(_))
+        | (BackupCursor::ItemRelations { .. }, BackupEntries::ItemRelations(_))
+        | (BackupCursor::Reviews { .. }, BackupEntries::Reviews(_))
+        | (BackupCursor::Questions { .. }, BackupEntries::Questions(_))
+        | (BackupCursor::QaVotes { .. }, BackupEntries::QaVotes(_))
+        | (BackupCursor::Wishlists { .. }, BackupEntries::Wishlists(_))
+        | (BackupCursor::Log { .. }, BackupEntries::Log(_)) => Ok(()),
+        (BackupCursor::ItemsInId { .. }, BackupEntries::ItemsInId(entries)) => {
+            for (item_id, item_key) in entries {
+                match ITEMS.with_borrow(|p| p.get(item_key)) {
+                    Some(item) if item.id == *item_id => {}
+                    Some(_) => {
+                        return invalid(format!(
+                            "Item id {} points at an item with another id",
+                            item_id
+                        ))
+                    }
+                    None => return invalid(format!("Item id {} points at no item", item_id)),
+                }
+            }
+            Ok(())
+        }
+        (cursor, _) => invalid(format!(
+            "Entries do not belong to the chunk at {:?}",
+            cursor
+        )),
+    }
+}
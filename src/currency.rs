@@ -0,0 +1,31 @@
+use common::unit::{Currency, Price};
+
+/// Parses an ISO 4217 currency code, e.g. `USD`, ignoring case.
+pub fn parse_currency(code: &str) -> Option<Currency> {
+    serde_json::from_value(serde_json::Value::String(code.trim().to_ascii_uppercase())).ok()
+}
+
+/// Returns the ISO 4217 code of a currency, e.g. `USD`.
+pub fn currency_code(currency: &Currency) -> String {
+    match serde_json::to_value(currency) {
+        Ok(serde_json::Value::String(code)) => code,
+        _ => String::new(),
+    }
+}
+
+/// Number of digits after the decimal point in amounts of the currency.
+pub fn minor_units(code: &str) -> usize {
+    match code {
+        "BIF" | "CLP" | "DJF" | "GNF" | "ISK" | "JPY" | "KMF" | "KRW" | "PYG" | "RWF" | "UGX"
+        | "VND" | "VUV" | "XAF" | "XOF" | "XPF" => 0,
+        "BHD" | "IQD" | "JOD" | "KWD" | "LYD" | "OMR" | "TND" => 3,
+        _ => 2,
+    }
+}
+
+/// Formats a price with the minor units of its currency followed by the currency code,
+/// e.g. `12.50 USD` or `1500 JPY`.
+pub fn format_price(price: &Price, currency: &Currency) -> String {
+    let code = currency_code(currency);
+    format!("{:.*} {}", minor_units(&code), price.value(), code)
+}
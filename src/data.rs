@@ -1,4 +1,4 @@
-use crate::STORE_DATA;
+use crate::{feed::ProductFeedSettings, locale::Locale, media::RenditionSettings, STORE_DATA};
 use candid::{CandidType, Decode, Deserialize, Encode};
 use ic_stable_structures::{cell::ValueError, storable::Bound, Storable};
 use common::store::{StoreId, StoreName};
@@ -14,6 +14,12 @@ pub enum StoreData {
 pub struct StoreDataV1 {
     pub id: StoreId,
     pub name: StoreName,
+    pub rendition: Option<RenditionSettings>,
+    pub feed: Option<ProductFeedSettings>,
+    // Locale of the untranslated content of items
+    pub default_locale: Option<Locale>,
+    // Whether buyers with a verified purchase can answer questions, besides staff
+    pub verified_buyer_answers: Option<bool>,
 }
 
 impl Storable for StoreData {
@@ -28,12 +34,46 @@ impl Storable for StoreData {
     const BOUND: Bound = Bound::Unbounded;
 }
 
+pub(crate) fn get_store_data() -> Option<StoreDataV1> {
+    STORE_DATA.with(|p| match p.borrow().get() {
+        StoreData::V1(store_data) => Some(store_data.clone()),
+        StoreData::None => None,
+    })
+}
+
+/// Applies `f` to the current store data, starting from defaults when none is set yet.
+fn modify_store_data(f: impl FnOnce(&mut StoreDataV1)) -> Result<StoreData, ValueError> {
+    let mut data = get_store_data().unwrap_or_default();
+    f(&mut data);
+
+    STORE_DATA.with_borrow_mut(|store_data| store_data.set(StoreData::V1(data)))
+}
+
 pub(crate) fn update_store_data(id: StoreId, name: StoreName) -> Result<StoreData, ValueError> {
-    let mut res: Result<StoreData, ValueError> = Ok(StoreData::None);
+    modify_store_data(|data| {
+        data.id = id;
+        data.name = name;
+    })
+}
 
-    STORE_DATA.with_borrow_mut(|store_data| {
-        res = store_data.set(StoreData::V1(StoreDataV1 { id, name }))
-    });
+pub(crate) fn update_rendition_settings(
+    rendition: Option<RenditionSettings>,
+) -> Result<StoreData, ValueError> {
+    modify_store_data(|data| data.rendition = rendition)
+}
+
+pub(crate) fn update_product_feed_settings(
+    feed: Option<ProductFeedSettings>,
+) -> Result<StoreData, ValueError> {
+    modify_store_data(|data| data.feed = feed)
+}
+
+pub(crate) fn update_default_locale(
+    default_locale: Option<Locale>,
+) -> Result<StoreData, ValueError> {
+    modify_store_data(|data| data.default_locale = default_locale)
+}
 
-    res
+pub(crate) fn update_verified_buyer_answers(enabled: bool) -> Result<StoreData, ValueError> {
+    modify_store_data(|data| data.verified_buyer_answers = Some(enabled))
 }
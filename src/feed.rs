@@ -0,0 +1,432 @@
+use crate::{
+    auth::{self, AuthErrorCode},
+    currency::format_price,
+    data,
+    item::{
+        attr::{AttrKeysV2, V1_DIMENSIONS},
+        image::ImageVecKey,
+        Item,
+    },
+    media::{self, RenditionFormat, RenditionProfile, RenditionSettings, RenditionSize},
+    ITEMS, ITEMS_IN_ID,
+};
+use candid::{CandidType, Deserialize, Principal};
+use common::{
+    item::{
+        attr::{AttrKey, Stock},
+        ItemId, ItemKey,
+    },
+    unit::{Currency, Price},
+};
+
+/// Largest number of items read for one page of a feed.
+pub const MAX_FEED_ITEMS: u64 = 50;
+
+/// Number of tags exported as custom labels, which is the limit of both Google and Meta.
+const CUSTOM_LABEL_COUNT: usize = 5;
+
+// Followed by one `custom_label_{n}` column per exported tag
+const CSV_HEADER: [&str; 12] = [
+    "id",
+    "item_group_id",
+    "title",
+    "description",
+    "link",
+    "image_link",
+    "price",
+    "availability",
+    "quantity_to_sell_on_facebook",
+    "brand",
+    "condition",
+    "variant_attributes",
+];
+
+#[derive(CandidType, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProductFeedFormat {
+    // RSS 2.0 with the Google Merchant `g:` namespace, also read by Meta catalogs
+    Rss,
+    Csv,
+}
+
+impl ProductFeedFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ProductFeedFormat::Rss => "application/rss+xml; charset=utf-8",
+            ProductFeedFormat::Csv => "text/csv; charset=utf-8",
+        }
+    }
+}
+
+/// Store-wide values every feed row needs but items do not hold.
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct ProductFeedSettings {
+    // URL of the product page with `{item_id}` and `{variant_id}` placeholders,
+    // e.g. `https://shop.example.com/items/{item_id}?variant={variant_id}`
+    pub link_template: String,
+    pub brand: Option<String>,
+    pub title: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct ProductFeedRequest {
+    pub format: ProductFeedFormat,
+    // Variants without a price in this currency are left out
+    pub currency: Currency,
+    pub offset: u64,
+    pub limit: u64,
+}
+
+/// One page of a feed. Pages are separate documents, each with its own header,
+/// so they can be uploaded to the merchant center as separate feed files.
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct ProductFeedPage {
+    pub content_type: String,
+    pub body: String,
+    pub row_count: u64,
+    // Offset of the next page, `None` on the last page
+    pub next_offset: Option<u64>,
+}
+
+/// A variant as exported to a feed.
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct ProductFeedRow {
+    pub id: String,
+    pub item_id: ItemId,
+    pub attr_keys: AttrKeysV2,
+    pub title: String,
+    pub description: String,
+    // Attribute name and value label of the variant
+    pub attrs: Vec<(String, String)>,
+    pub link: Option<String>,
+    pub image_link: Option<String>,
+    pub price: Price,
+    pub stock: Stock,
+    pub tags: Vec<String>,
+}
+
+/// Builds the id of a variant, which stays the same as long as its keys do,
+/// e.g. `42-0-1-0-0`.
+///
+/// Keys are padded to `V1_DIMENSIONS` and trailing default keys beyond it are dropped, so
+/// migrating an item to `ItemAttrsV2` or adding a dimension keeps the ids merchants indexed.
+pub fn variant_id(item_id: &ItemId, attr_keys: &AttrKeysV2) -> String {
+    let len = attr_keys
+        .0
+        .iter()
+        .rposition(|key| *key != AttrKey::default())
+        .map_or(0, |index| index + 1)
+        .max(V1_DIMENSIONS);
+
+    attr_keys
+        .normalized(len)
+        .0
+        .iter()
+        .fold(item_id.to_string(), |id, key| format!("{}-{}", id, key))
+}
+
+impl Item {
+    /// Returns a feed row for each variant priced in `currency`.
+    pub fn feed_rows(
+        &self,
+        currency: &Currency,
+        feed: Option<&ProductFeedSettings>,
+        rendition: Option<&RenditionSettings>,
+    ) -> Vec<ProductFeedRow> {
+        let attr_names: Vec<String> = self
+            .attr_indexes()
+            .into_iter()
+            .map(|index| index.map(|index| index.name.clone()).unwrap_or_default())
+            .collect();
+        let description = self.descriptions().join("\n");
+        let tags: Vec<String> = self.tags().iter().map(|tag| tag.to_string()).collect();
+
+        self.variants()
+            .into_iter()
+            .filter_map(|(attr_keys, data)| {
+                let price = self.effective_price(data, currency)?;
+                let id = variant_id(&self.id, &attr_keys);
+
+                let attrs: Vec<(String, String)> = attr_names
+                    .iter()
+                    .zip(self.get_attrs_index_values(&attr_keys))
+                    .filter_map(|(name, value)| Some((name.clone(), value?)))
+                    .collect();
+
+                let mut title = self.name.to_string();
+                if !attrs.is_empty() {
+                    let labels: Vec<&str> = attrs.iter().map(|(_, v)| v.as_str()).collect();
+                    title = format!("{} ({})", title, labels.join(", "));
+                }
+
+                let link = feed.map(|feed| {
+                    feed.link_template
+                        .replace("{item_id}", &self.id.to_string())
+                        .replace("{variant_id}", &id)
+                });
+
+                Some(ProductFeedRow {
+                    id,
+                    item_id: self.id,
+                    attr_keys,
+                    title,
+                    description: description.clone(),
+                    attrs,
+                    link,
+                    image_link: image_link(self, data.image_vec_key, rendition),
+                    price,
+                    stock: self.effective_stock(data),
+                    tags: tags.clone(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Links the original rendition of the base image of a variant, or the base image itself
+/// when it is outside the store media library or the store has no rendition settings.
+fn image_link(
+    item: &Item,
+    image_vec_key: ImageVecKey,
+    rendition: Option<&RenditionSettings>,
+) -> Option<String> {
+    rendition_link(item, image_vec_key, rendition).or_else(|| {
+        item.images()
+            .get_base_image(&image_vec_key)
+            .and_then(|image| media::media_url(&image))
+    })
+}
+
+fn rendition_link(
+    item: &Item,
+    image_vec_key: ImageVecKey,
+    rendition: Option<&RenditionSettings>,
+) -> Option<String> {
+    let settings = rendition?;
+    let media_id = item.images().get_base_media_id(&image_vec_key)?;
+    let asset = media::get_media(media_id)?;
+
+    let profile = RenditionProfile {
+        size: RenditionSize::Original,
+        formats: vec![RenditionFormat::Original],
+    };
+    settings
+        .renditions(media_id, &asset.metadata, &profile)
+        .into_iter()
+        .next()
+        .map(|rendition| rendition.url)
+}
+
+/// Exports a page of the product feed.
+///
+/// Pages are counted in items rather than variants, and at most `MAX_FEED_ITEMS` items are
+/// read per call, so a single call stays within the instruction limit of a query.
+pub(crate) fn export_product_feed(arg: &ProductFeedRequest) -> ProductFeedPage {
+    let limit = arg.limit.clamp(1, MAX_FEED_ITEMS);
+
+    let keys: Vec<ItemKey> = ITEMS_IN_ID.with_borrow(|p| {
+        p.iter()
+            .skip(arg.offset as usize)
+            .take(limit as usize + 1)
+            .map(|(_, key)| key)
+            .collect()
+    });
+    let next_offset = (keys.len() as u64 > limit).then_some(arg.offset + limit);
+
+    let store_data = data::get_store_data();
+    let feed = store_data.as_ref().and_then(|data| data.feed.as_ref());
+    let rendition = store_data.as_ref().and_then(|data| data.rendition.as_ref());
+
+    let rows: Vec<ProductFeedRow> = ITEMS.with_borrow(|p| {
+        keys.iter()
+            .take(limit as usize)
+            .filter_map(|key| p.get(key))
+            .flat_map(|item| item.feed_rows(&arg.currency, feed, rendition))
+            .collect()
+    });
+
+    let body = match arg.format {
+        ProductFeedFormat::Rss => to_rss(&rows, &arg.currency, feed),
+        ProductFeedFormat::Csv => to_csv(&rows, &arg.currency, feed),
+    };
+
+    ProductFeedPage {
+        content_type: arg.format.content_type().to_string(),
+        body,
+        row_count: rows.len() as u64,
+        next_offset,
+    }
+}
+
+fn availability(stock: Stock) -> &'static str {
+    if stock > 0 {
+        "in stock"
+    } else {
+        "out of stock"
+    }
+}
+
+fn escape_xml(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn to_rss(
+    rows: &[ProductFeedRow],
+    currency: &Currency,
+    feed: Option<&ProductFeedSettings>,
+) -> String {
+    let title = feed
+        .and_then(|feed| feed.title.clone())
+        .or_else(|| data::get_store_data().map(|data| data.name.to_string()))
+        .unwrap_or_default();
+
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <rss version=\"2.0\" xmlns:g=\"http://base.google.com/ns/1.0\">\n<channel>\n",
+    );
+    xml.push_str(&format!("<title>{}</title>\n", escape_xml(&title)));
+
+    for row in rows {
+        xml.push_str("<item>\n");
+
+        let mut element = |name: &str, value: &str| {
+            xml.push_str(&format!("<g:{0}>{1}</g:{0}>\n", name, escape_xml(value)));
+        };
+        element("id", &row.id);
+        element("item_group_id", &row.item_id.to_string());
+        element("title", &row.title);
+        element("description", &row.description);
+        if let Some(link) = &row.link {
+            element("link", link);
+        }
+        if let Some(image_link) = &row.image_link {
+            element("image_link", image_link);
+        }
+        element("price", &format_price(&row.price, currency));
+        element("availability", availability(row.stock));
+        element("quantity_to_sell_on_facebook", &row.stock.to_string());
+        if let Some(brand) = feed.and_then(|feed| feed.brand.as_ref()) {
+            element("brand", brand);
+        }
+        element("condition", "new");
+        for (i, tag) in row.tags.iter().take(CUSTOM_LABEL_COUNT).enumerate() {
+            element(&format!("custom_label_{}", i), tag);
+        }
+
+        for (name, value) in &row.attrs {
+            xml.push_str(&format!(
+                "<g:product_detail><g:attribute_name>{}</g:attribute_name>\
+                 <g:attribute_value>{}</g:attribute_value></g:product_detail>\n",
+                escape_xml(name),
+                escape_xml(value)
+            ));
+        }
+
+        xml.push_str("</item>\n");
+    }
+
+    xml.push_str("</channel>\n</rss>\n");
+    xml
+}
+
+fn escape_csv(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn to_csv(
+    rows: &[ProductFeedRow],
+    currency: &Currency,
+    feed: Option<&ProductFeedSettings>,
+) -> String {
+    let mut header: Vec<String> = CSV_HEADER.iter().map(|column| column.to_string()).collect();
+    header.extend((0..CUSTOM_LABEL_COUNT).map(|i| format!("custom_label_{}", i)));
+
+    let mut csv = header.join(",");
+    csv.push('\n');
+
+    let brand = feed.and_then(|feed| feed.brand.clone()).unwrap_or_default();
+
+    for row in rows {
+        let variant_attributes: Vec<String> = row
+            .attrs
+            .iter()
+            .map(|(name, value)| format!("{}:{}", name, value))
+            .collect();
+
+        let mut fields = vec![
+            row.id.clone(),
+            row.item_id.to_string(),
+            row.title.clone(),
+            row.description.clone(),
+            row.link.clone().unwrap_or_default(),
+            row.image_link.clone().unwrap_or_default(),
+            format_price(&row.price, currency),
+            availability(row.stock).to_string(),
+            row.stock.to_string(),
+            brand.clone(),
+            "new".to_string(),
+            variant_attributes.join(";"),
+        ];
+        fields
+            .extend((0..CUSTOM_LABEL_COUNT).map(|i| row.tags.get(i).cloned().unwrap_or_default()));
+
+        let fields: Vec<String> = fields.iter().map(|field| escape_csv(field)).collect();
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+
+    csv
+}
+
+pub(crate) fn update_product_feed_settings(
+    caller: Principal,
+    settings: Option<ProductFeedSettings>,
+) -> Result<(), (AuthErrorCode, String)> {
+    auth::require_owner::<AuthErrorCode>(caller)?;
+    let _ = data::update_product_feed_settings(settings);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item_id(id: &str) -> ItemId {
+        serde_json::from_value(serde_json::Value::String(id.to_string())).unwrap()
+    }
+
+    fn keys(keys: Vec<u8>) -> AttrKeysV2 {
+        AttrKeysV2(keys.into_iter().map(Into::into).collect())
+    }
+
+    #[test]
+    fn variant_id_is_kept_across_dimension_counts() {
+        let id = item_id("42");
+
+        assert_eq!(variant_id(&id, &keys(vec![0, 1, 0, 0])), "42-0-1-0-0");
+        assert_eq!(variant_id(&id, &keys(vec![0, 1])), "42-0-1-0-0");
+        assert_eq!(variant_id(&id, &keys(vec![0, 1, 0, 0, 0])), "42-0-1-0-0");
+    }
+
+    #[test]
+    fn variant_id_keeps_keys_beyond_v1_dimensions() {
+        let id = item_id("42");
+
+        assert_eq!(variant_id(&id, &keys(vec![0, 1, 0, 0, 2])), "42-0-1-0-0-2");
+    }
+}
@@ -0,0 +1,185 @@
+mod catalog;
+mod feed;
+
+use crate::asset;
+use candid::{CandidType, Deserialize, Func};
+use serde_bytes::ByteBuf;
+use std::collections::BTreeMap;
+
+pub type HeaderField = (String, String);
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<HeaderField>,
+    pub body: ByteBuf,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: Vec<HeaderField>,
+    pub body: ByteBuf,
+    pub streaming_strategy: Option<StreamingStrategy>,
+}
+
+impl HttpResponse {
+    pub fn new(status_code: u16, headers: Vec<HeaderField>, body: Vec<u8>) -> Self {
+        Self {
+            status_code,
+            headers,
+            body: ByteBuf::from(body),
+            streaming_strategy: None,
+        }
+    }
+
+    pub fn error(status_code: u16, message: &str) -> Self {
+        Self::new(
+            status_code,
+            vec![(
+                "Content-Type".to_string(),
+                "text/plain; charset=utf-8".to_string(),
+            )],
+            message.as_bytes().to_vec(),
+        )
+    }
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub enum StreamingStrategy {
+    Callback {
+        callback: Func,
+        token: StreamingCallbackToken,
+    },
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct StreamingCallbackToken {
+    pub asset_id: asset::AssetId,
+    pub chunk_index: u32,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct StreamingCallbackHttpResponse {
+    pub body: ByteBuf,
+    pub token: Option<StreamingCallbackToken>,
+}
+
+impl HttpRequest {
+    /// Returns the path of the url without the query string.
+    pub fn path(&self) -> &str {
+        self.url.split('?').next().unwrap_or_default()
+    }
+
+    /// Returns the percent-decoded parameters of the query string. `+` is read as a space.
+    pub fn query(&self) -> BTreeMap<String, String> {
+        self.url
+            .split_once('?')
+            .map(|(_, query)| query)
+            .unwrap_or_default()
+            .split('&')
+            .filter_map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (!key.is_empty()).then(|| (percent_decode(key), percent_decode(value)))
+            })
+            .collect()
+    }
+
+    /// Whether the request was made through the raw domain, e.g. `{id}.raw.icp0.io`,
+    /// where boundary nodes pass responses on without checking their certification.
+    pub fn is_raw_domain(&self) -> bool {
+        self.header("Host").is_some_and(|host| {
+            host.split(':')
+                .next()
+                .unwrap_or_default()
+                .split('.')
+                .nth(1)
+                .is_some_and(|label| label.eq_ignore_ascii_case("raw"))
+        })
+    }
+
+    /// Returns the value of the first header with the given name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Decodes `%XX` escapes and reads `+` as a space. Invalid escapes are kept as they are.
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => match value
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                Some(byte) => {
+                    decoded.push(byte);
+                    i += 2;
+                }
+                None => decoded.push(b'%'),
+            },
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Rejects a request for an uncertified response made through a certified domain,
+/// pointing to the same path on the raw domain instead.
+fn require_raw_domain(req: &HttpRequest) -> Option<HttpResponse> {
+    if req.is_raw_domain() {
+        return None;
+    }
+
+    let message = match req.header("Host").and_then(|host| host.split_once('.')) {
+        Some((canister, domain)) => format!(
+            "This response is not certified, fetch it from https://{}.raw.{}{}",
+            canister, domain, req.url
+        ),
+        None => "This response is not certified, fetch it from the raw domain".to_string(),
+    };
+    Some(HttpResponse::error(421, &message))
+}
+
+pub(crate) fn http_request(req: HttpRequest) -> HttpResponse {
+    if req.method != "GET" && req.method != "HEAD" {
+        return HttpResponse::error(405, "Method not allowed");
+    }
+
+    let path = req.path();
+    if let Some(asset_id) = path.strip_prefix(asset::ASSET_PATH_PREFIX) {
+        return match asset_id.parse() {
+            Ok(asset_id) => asset::serve_asset(asset_id),
+            Err(_) => HttpResponse::error(404, "Not found"),
+        };
+    }
+
+    if let Some(rest) = path.strip_prefix(catalog::ITEMS_PATH_PREFIX) {
+        if rest.is_empty() || rest.starts_with('/') {
+            if let Some(res) = require_raw_domain(&req) {
+                return res;
+            }
+            return catalog::serve(&req, rest);
+        }
+    }
+
+    if let Some(rest) = path.strip_prefix(feed::FEEDS_PATH_PREFIX) {
+        if let Some(res) = require_raw_domain(&req) {
+            return res;
+        }
+        return feed::serve(&req, rest);
+    }
+
+    HttpResponse::error(404, "Not found")
+}
@@ -0,0 +1,204 @@
+use super::{HttpRequest, HttpResponse};
+use crate::{
+    currency,
+    item::{self, attr::AttrKeysV2, spec::unit::UnitSystem, AttrRequestV2, ItemPageRequestV2},
+    locale::Locale,
+    ITEMS_IN_ID,
+};
+use common::item::{ItemId, ItemPageFromStoreErrorCode};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Items are served at this path, optionally followed by an item id.
+pub const ITEMS_PATH_PREFIX: &str = "/items";
+
+/// Largest page of items returned by `/items`.
+pub const MAX_LIST_LIMIT: u64 = 100;
+
+const JSON_CACHE_CONTROL: &str = "public, max-age=60";
+
+#[derive(Serialize)]
+struct JsonError<'a> {
+    code: &'a str,
+    message: &'a str,
+}
+
+fn json_response<T: Serialize>(status_code: u16, value: &T) -> HttpResponse {
+    match serde_json::to_vec(value) {
+        Ok(body) => HttpResponse::new(
+            status_code,
+            vec![
+                (
+                    "Content-Type".to_string(),
+                    "application/json; charset=utf-8".to_string(),
+                ),
+                ("Cache-Control".to_string(), JSON_CACHE_CONTROL.to_string()),
+            ],
+            body,
+        ),
+        Err(_) => HttpResponse::error(500, "Failed to encode the response"),
+    }
+}
+
+fn json_error(status_code: u16, code: &str, message: &str) -> HttpResponse {
+    json_response(status_code, &JsonError { code, message })
+}
+
+fn status_code_of(code: &ItemPageFromStoreErrorCode) -> u16 {
+    if matches!(code, ItemPageFromStoreErrorCode::ItemNotFound) {
+        404
+    } else if matches!(code, ItemPageFromStoreErrorCode::NoAvailableAttr) {
+        422
+    } else {
+        500
+    }
+}
+
+/// Whether the `Accept` header allows a JSON response. A missing header allows anything.
+fn accepts_json(req: &HttpRequest) -> bool {
+    match req.header("Accept") {
+        Some(accept) => accept.split(',').any(|media_range| {
+            let media_range = media_range.split(';').next().unwrap_or_default().trim();
+            matches!(media_range, "application/json" | "application/*" | "*/*")
+        }),
+        None => true,
+    }
+}
+
+/// Reads the preferred locales from the `locales` parameter, e.g. `locales=ja-JP,en`,
+/// or from the `Accept-Language` header when the parameter is missing.
+fn preferred_locales(req: &HttpRequest, query: &BTreeMap<String, String>) -> Vec<Locale> {
+    if let Some(locales) = query.get("locales") {
+        return locales
+            .split(',')
+            .map(|locale| locale.trim().to_string())
+            .filter(|locale| !locale.is_empty())
+            .collect();
+    }
+
+    // Entries are listed in order of preference by browsers, so the weights are ignored
+    req.header("Accept-Language")
+        .unwrap_or_default()
+        .split(',')
+        .map(|range| {
+            range
+                .split(';')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_string()
+        })
+        .filter(|range| !range.is_empty() && range != "*")
+        .collect()
+}
+
+/// Parses an item id in the form it is listed in, and checks that the item exists.
+fn find_item_id(id: &str) -> Option<ItemId> {
+    let item_id: ItemId = serde_json::from_value(serde_json::Value::String(id.to_string()))
+        .or_else(|_| serde_json::from_str(id))
+        .ok()?;
+
+    ITEMS_IN_ID
+        .with_borrow(|p| p.contains_key(&item_id))
+        .then_some(item_id)
+}
+
+/// Serves `/items` and `/items/{id}` as JSON.
+///
+/// Responses are not certified, so `http_request` only routes requests
+/// made through the raw domain here.
+pub(crate) fn serve(req: &HttpRequest, path: &str) -> HttpResponse {
+    if !accepts_json(req) {
+        return HttpResponse::error(406, "Only application/json is available");
+    }
+
+    let query = req.query();
+
+    match path.trim_end_matches('/') {
+        "" => serve_item_list(&query),
+        id => match id.strip_prefix('/') {
+            Some(id) if !id.contains('/') => serve_item_page(req, id, &query),
+            _ => json_error(404, "NotFound", "Not found"),
+        },
+    }
+}
+
+fn serve_item_list(query: &BTreeMap<String, String>) -> HttpResponse {
+    let offset = match query.get("offset").map(|v| v.parse::<u64>()) {
+        Some(Ok(offset)) => offset,
+        Some(Err(_)) => return json_error(400, "BadRequest", "offset must be a number"),
+        None => 0,
+    };
+    let limit = match query.get("limit").map(|v| v.parse::<u64>()) {
+        Some(Ok(limit)) => limit.min(MAX_LIST_LIMIT),
+        Some(Err(_)) => return json_error(400, "BadRequest", "limit must be a number"),
+        None => MAX_LIST_LIMIT,
+    };
+
+    json_response(200, &item::list_items(offset, limit, None))
+}
+
+fn serve_item_page(req: &HttpRequest, id: &str, query: &BTreeMap<String, String>) -> HttpResponse {
+    let Some(item_id) = find_item_id(id) else {
+        return json_error(
+            404,
+            "ItemNotFound",
+            &format!("Item with id {} not found", id),
+        );
+    };
+
+    let keys: Result<Vec<u8>, _> = query
+        .get("attrs")
+        .filter(|attrs| !attrs.is_empty())
+        .map_or(Ok(Vec::new()), |attrs| {
+            attrs.split(',').map(|key| key.trim().parse()).collect()
+        });
+    let Ok(keys) = keys else {
+        return json_error(400, "BadRequest", "attrs must be comma-separated numbers");
+    };
+
+    let changed_key_index = match query.get("changed").map(|v| v.parse::<u8>()) {
+        Some(Ok(index)) => Some(index),
+        Some(Err(_)) => return json_error(400, "BadRequest", "changed must be a number"),
+        None => None,
+    };
+
+    let Some(currency) = query
+        .get("currency")
+        .and_then(|code| currency::parse_currency(code))
+    else {
+        return json_error(
+            400,
+            "BadRequest",
+            "currency is missing or not a currency code",
+        );
+    };
+
+    let unit_system = match query.get("units").map(String::as_str) {
+        Some("metric") => Some(UnitSystem::Metric),
+        Some("imperial") => Some(UnitSystem::Imperial),
+        Some(_) => {
+            return json_error(400, "BadRequest", "units must be metric or imperial");
+        }
+        None => None,
+    };
+
+    let arg = ItemPageRequestV2 {
+        item_id,
+        attr: AttrRequestV2 {
+            keys: AttrKeysV2(keys),
+            changed_key_index,
+            include_availability: Some(query.get("availability").is_some_and(|v| v == "true")),
+        },
+        currency,
+        rendition: None,
+        locales: Some(preferred_locales(req, query)),
+        unit_system,
+        include_related: Some(query.get("related").is_some_and(|v| v == "true")),
+    };
+
+    match item::get_item_page_data_v2(&arg) {
+        Ok(res) => json_response(200, &res),
+        Err((code, message)) => json_error(status_code_of(&code), &format!("{:?}", code), &message),
+    }
+}
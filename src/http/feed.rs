@@ -0,0 +1,53 @@
+use super::{HttpRequest, HttpResponse};
+use crate::{
+    currency,
+    feed::{self, ProductFeedFormat, ProductFeedRequest, MAX_FEED_ITEMS},
+};
+
+/// Feeds are served at this path followed by `products.xml` or `products.csv`.
+pub const FEEDS_PATH_PREFIX: &str = "/feeds/";
+
+/// Serves a page of the product feed, e.g. `/feeds/products.xml?currency=USD&offset=50`.
+///
+/// The offset of the next page is sent in the `X-Next-Offset` header.
+/// Feeds are not certified, so they are only served on the raw domain.
+pub(crate) fn serve(req: &HttpRequest, path: &str) -> HttpResponse {
+    let format = match path {
+        "products.xml" => ProductFeedFormat::Rss,
+        "products.csv" => ProductFeedFormat::Csv,
+        _ => return HttpResponse::error(404, "Not found"),
+    };
+
+    let query = req.query();
+
+    let Some(currency) = query
+        .get("currency")
+        .and_then(|code| currency::parse_currency(code))
+    else {
+        return HttpResponse::error(400, "currency is missing or not a currency code");
+    };
+    let offset = match query.get("offset").map(|v| v.parse::<u64>()) {
+        Some(Ok(offset)) => offset,
+        Some(Err(_)) => return HttpResponse::error(400, "offset must be a number"),
+        None => 0,
+    };
+    let limit = match query.get("limit").map(|v| v.parse::<u64>()) {
+        Some(Ok(limit)) => limit,
+        Some(Err(_)) => return HttpResponse::error(400, "limit must be a number"),
+        None => MAX_FEED_ITEMS,
+    };
+
+    let page = feed::export_product_feed(&ProductFeedRequest {
+        format,
+        currency,
+        offset,
+        limit,
+    });
+
+    let mut headers = vec![("Content-Type".to_string(), page.content_type)];
+    if let Some(next_offset) = page.next_offset {
+        headers.push(("X-Next-Offset".to_string(), next_offset.to_string()));
+    }
+
+    HttpResponse::new(200, headers, page.body.into_bytes())
+}
@@ -1,25 +1,56 @@
-use crate::data::StoreData;
+use crate::{
+    auth::{self, AuthErrorCode},
+    data::{self, StoreData},
+    locale::{Locale, LocaleChain},
+    media::{MediaRendition, RenditionProfile},
+};
 
 use super::{ITEMS, ITEMS_IN_ID, STORE_DATA};
-use candid::{CandidType, Decode, Deserialize, Encode};
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
 use ic_stable_structures::{storable::Bound, Storable};
+use common::{
+    item::{attr::Stock, spec::SpecResponse, MediaDataWithCaption},
+    store::StoreName,
+    unit::Price,
+};
 pub use common::{
     item::{
-        attr::{AttrKeys, AttrStatusResponse, AttrStatusesResponse},
+        attr::{AttrKey, AttrKeys, AttrStatusResponse, AttrStatusesResponse},
         ItemCoreDataRequest, ItemId, ItemKey, ItemName, ItemPageFromStoreErrorCode,
         ItemPageRequestToStoreCanister, ItemPageResponseFromStoreCanister,
         ItemPageStaticDataFromStoreCanister, Tag,
     },
     unit::Currency,
 };
+use serde::Serialize;
 use std::borrow::Cow;
 
 pub mod attr;
-use attr::{AttrCoreSpecificDataResponse, AttrSpecificDataResponse, ItemAttrsV1};
+use attr::{
+    to_v1_indexes_response, to_v1_statuses_response, AttrCoreSpecificDataResponse, AttrIndex,
+    AttrIndexesResponseV2, AttrKeysV2, AttrSpecificData, AttrSpecificDataResponse,
+    AttrStatusesResponseV2, ItemAttrsV1, ItemAttrsV2, V1_DIMENSIONS,
+};
+pub mod bundle;
+use bundle::{BundleComponentResponse, ItemBundle};
+pub mod compare;
+pub mod filter;
 pub mod image;
-use image::ItemImagesV1;
+use image::{GalleryOperation, ImageKey, ImageVecKey, ItemImagesV1, MediaKind};
+pub mod import;
+pub mod question;
+pub mod relation;
+use relation::RelatedItem;
+pub mod review;
+use review::RatingSummary;
+pub mod revision;
+use revision::{ItemRevisionReason, RevisionNumber};
 pub mod spec;
-use spec::ItemSpecsV1;
+use spec::{unit::UnitSystem, ItemSpecsV1};
+pub mod translation;
+use translation::ItemTranslations;
+pub mod variant;
+use variant::{VariantAvailabilityMatrix, VariantFallbackStrategy};
 
 nest! {
     /// Represents an item with its associated data.
@@ -35,8 +66,19 @@ nest! {
                 images: ItemImagesV1,
                 specs: ItemSpecsV1,
                 attrs: ItemAttrsV1,
-            }
+            },
+            V2 {
+                descriptions: Vec<String>,
+                tags: Vec<Tag>,
+                images: ItemImagesV1,
+                specs: ItemSpecsV1,
+                attrs: ItemAttrsV2,
+            },
         },
+        pub fallback_strategy: Option<VariantFallbackStrategy>,
+        pub translations: Option<ItemTranslations>,
+        // Components of the item when it is a bundle
+        pub bundle: Option<ItemBundle>,
     }
 }
 
@@ -52,102 +94,574 @@ impl Storable for Item {
     const BOUND: Bound = Bound::Unbounded;
 }
 
-impl Item {
-    /// Retrieves specific attribute data for an item based on provided keys and currency.
-    pub fn get_attr_data(
-        &self,
-        attr_keys: &AttrKeys,
-        currency: &Currency,
-    ) -> Option<AttrSpecificDataResponse> {
-        match &self.version {
+impl ItemVersion {
+    /// Migrates V1 data to V2 without changing any keys. V2 data is returned unchanged.
+    pub fn try_into_v2(self) -> Result<Self, String> {
+        let version = match self {
             ItemVersion::V1 {
+                descriptions,
+                tags,
+                images,
+                specs,
                 attrs,
+            } => ItemVersion::V2 {
+                descriptions,
+                tags,
                 images,
                 specs,
-                ..
-            } => {
-                let attr_data = attrs.map.get(attr_keys)?;
-                let price = *attr_data.price.get(currency)?;
-                let image_vec = images.get_index_vec(&attr_data.image_vec_key)?;
-                let specs = specs.get_specs(&attr_data.spec_keys);
-
-                let res = AttrSpecificDataResponse {
-                    stock: attr_data.stock,
-                    price,
-                    image_vec,
-                    specs,
-                    sale: attr_data.sale,
-                };
-
-                Some(res)
+                attrs: attrs.try_into()?,
+            },
+            v2 => v2,
+        };
+
+        Ok(version)
+    }
+}
+
+impl Item {
+    pub fn descriptions(&self) -> &Vec<String> {
+        match &self.version {
+            ItemVersion::V1 { descriptions, .. } | ItemVersion::V2 { descriptions, .. } => {
+                descriptions
             }
         }
     }
 
-    pub fn get_attr_statuses(&self, attr_keys: &AttrKeys) -> AttrStatusesResponse {
-        let mut result = [Vec::new(), Vec::new(), Vec::new(), Vec::new()];
+    pub fn tags(&self) -> &Vec<Tag> {
+        match &self.version {
+            ItemVersion::V1 { tags, .. } | ItemVersion::V2 { tags, .. } => tags,
+        }
+    }
+
+    pub fn images(&self) -> &ItemImagesV1 {
+        match &self.version {
+            ItemVersion::V1 { images, .. } | ItemVersion::V2 { images, .. } => images,
+        }
+    }
+
+    pub fn images_mut(&mut self) -> &mut ItemImagesV1 {
+        match &mut self.version {
+            ItemVersion::V1 { images, .. } | ItemVersion::V2 { images, .. } => images,
+        }
+    }
+
+    pub fn specs(&self) -> &ItemSpecsV1 {
+        match &self.version {
+            ItemVersion::V1 { specs, .. } | ItemVersion::V2 { specs, .. } => specs,
+        }
+    }
+
+    /// Returns the definition of each dimension.
+    /// V1 items always have four slots, some of which may be unused.
+    pub fn attr_indexes(&self) -> Vec<Option<&AttrIndex>> {
+        match &self.version {
+            ItemVersion::V1 { attrs, .. } => attrs.indexes.iter().collect(),
+            ItemVersion::V2 { attrs, .. } => attrs.indexes.iter().map(Some).collect(),
+        }
+    }
+
+    pub fn dimensions(&self) -> usize {
+        match &self.version {
+            ItemVersion::V1 { .. } => V1_DIMENSIONS,
+            ItemVersion::V2 { attrs, .. } => attrs.indexes.len(),
+        }
+    }
+
+    /// Looks up the data of a single variant.
+    pub fn get_variant(&self, attr_keys: &AttrKeysV2) -> Option<&AttrSpecificData> {
+        if !attr_keys.fits(self.dimensions()) {
+            return None;
+        }
 
         match &self.version {
             ItemVersion::V1 { attrs, .. } => {
-                let indexes = attrs.get_attrs_indexes_result();
-
-                indexes
-                    .iter()
-                    .enumerate()
-                    .for_each(|(i, index)| if let Some(index) = index {
-                        let mut each_result = Vec::new();
-
-                        index.value.iter().enumerate().for_each(|(j, _)| {
-                            let attr_keys = attr_keys.replace(i, &(j as u8)).unwrap();
-                            match attrs.map.get(&attr_keys) {
-                                Some(attr_data) => {
-                                    let is_in_stock = attr_data.stock > 0;
-                                    each_result.push(Some(AttrStatusResponse { is_in_stock }));
-                                }
-                                None => {
-                                    each_result.push(None);
-                                }
-                            }
-                        });
-
-                        result[i] = each_result;
-                    });
+                attrs.map.get(&attr_keys.normalized(V1_DIMENSIONS).to_v1()?)
+            }
+            ItemVersion::V2 { attrs, .. } => {
+                attrs.map.get(&attr_keys.normalized(attrs.indexes.len()))
+            }
+        }
+    }
+
+    /// Sets the data of a single variant.
+    /// Returns `false` when the keys cannot be expressed in the item's version.
+    pub fn insert_variant(&mut self, attr_keys: &AttrKeysV2, data: AttrSpecificData) -> bool {
+        if !attr_keys.fits(self.dimensions()) {
+            return false;
+        }
+
+        match &mut self.version {
+            ItemVersion::V1 { attrs, .. } => match attr_keys.normalized(V1_DIMENSIONS).to_v1() {
+                Some(keys) => {
+                    attrs.map.insert(keys, data);
+                    true
+                }
+                None => false,
+            },
+            ItemVersion::V2 { attrs, .. } => {
+                let keys = attr_keys.normalized(attrs.indexes.len());
+                attrs.map.insert(keys, data);
+                true
             }
         }
+    }
+
+    /// Lists every combination of the item's dimension keys, whether or not it has data.
+    pub fn attr_key_combinations(&self) -> Vec<AttrKeysV2> {
+        attr::attr_key_combinations(&self.attr_indexes())
+    }
+
+    /// Lists every variant of the item with its keys.
+    pub fn variants(&self) -> Vec<(AttrKeysV2, &AttrSpecificData)> {
+        match &self.version {
+            ItemVersion::V1 { attrs, .. } => attrs
+                .map
+                .iter()
+                .map(|(keys, data)| (keys.into(), data))
+                .collect(),
+            ItemVersion::V2 { attrs, .. } => attrs
+                .map
+                .iter()
+                .map(|(keys, data)| (keys.clone(), data))
+                .collect(),
+        }
+    }
+
+    pub fn get_attrs_indexes_result(&self) -> AttrIndexesResponseV2 {
+        match &self.version {
+            ItemVersion::V1 { attrs, .. } => attrs.get_attrs_indexes_result().into_iter().collect(),
+            ItemVersion::V2 { attrs, .. } => attrs.get_attrs_indexes_result(),
+        }
+    }
+
+    pub fn get_attrs_index_values(&self, attr_keys: &AttrKeysV2) -> Vec<Option<String>> {
+        match &self.version {
+            ItemVersion::V1 { attrs, .. } => attrs.get_attrs_index_values(
+                attr_keys
+                    .normalized(V1_DIMENSIONS)
+                    .to_v1()
+                    .unwrap_or_default(),
+            ),
+            ItemVersion::V2 { attrs, .. } => attrs.get_attrs_index_values(attr_keys),
+        }
+    }
+
+    /// Retrieves specific attribute data for an item based on provided keys and currency.
+    pub fn get_attr_data(
+        &self,
+        attr_keys: &AttrKeysV2,
+        currency: &Currency,
+    ) -> Option<AttrSpecificDataResponse> {
+        let attr_data = self.get_variant(attr_keys)?;
+        let price = self.effective_price(attr_data, currency)?;
+        let image_vec = self.images().get_index_vec(&attr_data.image_vec_key)?;
+        let specs = self.specs().get_specs(&attr_data.spec_keys);
+
+        let res = AttrSpecificDataResponse {
+            stock: self.effective_stock(attr_data),
+            price,
+            image_vec,
+            specs,
+            sale: attr_data.sale,
+        };
+
+        Some(res)
+    }
 
-        result
+    pub fn get_attr_statuses(&self, attr_keys: &AttrKeysV2) -> AttrStatusesResponseV2 {
+        self.attr_indexes()
+            .iter()
+            .enumerate()
+            .map(|(i, index)| match index {
+                Some(index) => (0..index.map.len())
+                    .map(|j| {
+                        let attr_keys = attr_keys.replace(i, j as AttrKey);
+                        self.get_variant(&attr_keys)
+                            .map(|attr_data| AttrStatusResponse {
+                                is_in_stock: self.effective_stock(attr_data) > 0,
+                            })
+                    })
+                    .collect(),
+                None => Vec::new(),
+            })
+            .collect()
     }
 
     /// Retrieves core attribute data for an item, which includes stock, price, base image, and sale status.
     pub fn get_attr_core_data(
         &self,
-        attr_keys: &AttrKeys,
+        attr_keys: &AttrKeysV2,
         currency: &Currency,
     ) -> Option<AttrCoreSpecificDataResponse> {
-        match &self.version {
-            ItemVersion::V1 { attrs, images, .. } => {
-                let attr_data = attrs.map.get(attr_keys)?;
-                let price = *attr_data.price.get(currency)?;
-                let image = images.get_base_image(&attr_data.image_vec_key)?.clone();
-
-                let res = AttrCoreSpecificDataResponse {
-                    stock: attr_data.stock,
-                    price,
-                    image,
-                    sale: attr_data.sale,
-                };
-
-                Some(res)
+        let attr_data = self.get_variant(attr_keys)?;
+        let price = self.effective_price(attr_data, currency)?;
+        let image = self.images().get_base_image(&attr_data.image_vec_key)?;
+
+        let res = AttrCoreSpecificDataResponse {
+            stock: self.effective_stock(attr_data),
+            price,
+            image,
+            sale: attr_data.sale,
+        };
+
+        Some(res)
+    }
+}
+
+/// Short description of an item for listings and links to other items.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct ItemSummary {
+    pub id: ItemId,
+    pub name: ItemName,
+    // Keys of the variant the image, price and stock are taken from
+    pub attr_keys: Option<AttrKeysV2>,
+    pub base_image: Option<MediaDataWithCaption>,
+    pub price: Option<Price>,
+    pub is_in_stock: bool,
+}
+
+impl Item {
+    /// Summarizes the item through its default variant, or its first variant when
+    /// there is no default one. The price is only set when `currency` is given.
+    pub fn summary(&self, currency: Option<&Currency>) -> ItemSummary {
+        let default_keys = AttrKeysV2::default().normalized(self.dimensions());
+        let variant = match self.get_variant(&default_keys) {
+            Some(data) => Some((default_keys, data)),
+            None => self.variants().into_iter().next(),
+        };
+
+        let is_in_stock = self
+            .variants()
+            .iter()
+            .any(|(_, data)| self.effective_stock(data) > 0);
+
+        ItemSummary {
+            id: self.id,
+            name: self.name.clone(),
+            base_image: variant
+                .as_ref()
+                .and_then(|(_, data)| self.images().get_base_image(&data.image_vec_key)),
+            price: variant
+                .as_ref()
+                .zip(currency)
+                .and_then(|((_, data), currency)| self.effective_price(data, currency)),
+            is_in_stock,
+            attr_keys: variant.map(|(keys, _)| keys),
+        }
+    }
+}
+
+/// Lists items in id order, skipping the first `offset`.
+pub(crate) fn list_items(offset: u64, limit: u64, currency: Option<&Currency>) -> Vec<ItemSummary> {
+    let keys: Vec<ItemKey> = ITEMS_IN_ID.with_borrow(|p| {
+        p.iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .map(|(_, key)| key)
+            .collect()
+    });
+
+    ITEMS.with_borrow(|p| {
+        keys.iter()
+            .filter_map(|key| p.get(key))
+            .map(|item| item.summary(currency))
+            .collect()
+    })
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum ItemUpdateErrorCode {
+    Unauthorized,
+    ItemNotFound,
+    RevisionNotFound,
+    InvalidItem,
+    InvalidGallery,
+}
+
+impl From<AuthErrorCode> for ItemUpdateErrorCode {
+    fn from(code: AuthErrorCode) -> Self {
+        match code {
+            AuthErrorCode::Unauthorized => ItemUpdateErrorCode::Unauthorized,
+        }
+    }
+}
+
+/// Partial update of an item. Fields left as `None` are kept as they are.
+#[derive(CandidType, Deserialize, Debug, Clone, Default)]
+pub struct ItemPatch {
+    pub name: Option<ItemName>,
+    pub descriptions: Option<Vec<String>>,
+    pub tags: Option<Vec<Tag>>,
+    pub fallback_strategy: Option<VariantFallbackStrategy>,
+    // Replaces every translation of the item
+    pub translations: Option<ItemTranslations>,
+}
+
+impl ItemPatch {
+    fn apply(self, item: &mut Item) {
+        if let Some(name) = self.name {
+            item.name = name;
+        }
+        if let Some(fallback_strategy) = self.fallback_strategy {
+            item.fallback_strategy = Some(fallback_strategy);
+        }
+        if let Some(translations) = self.translations {
+            item.translations = Some(translations);
+        }
+
+        match &mut item.version {
+            ItemVersion::V1 {
+                descriptions, tags, ..
+            }
+            | ItemVersion::V2 {
+                descriptions, tags, ..
+            } => {
+                if let Some(new_descriptions) = self.descriptions {
+                    *descriptions = new_descriptions;
+                }
+                if let Some(new_tags) = self.tags {
+                    *tags = new_tags;
+                }
             }
         }
     }
 }
 
+/// Fetches the live item with the given id.
+pub(crate) fn get_item(item_id: &ItemId) -> Result<Item, (ItemUpdateErrorCode, String)> {
+    let item_key = ITEMS_IN_ID.with(|p| p.borrow().get(item_id)).ok_or((
+        ItemUpdateErrorCode::ItemNotFound,
+        format!("Item with id {} not found in the store", item_id),
+    ))?;
+
+    ITEMS.with(|p| p.borrow().get(&item_key)).ok_or((
+        ItemUpdateErrorCode::ItemNotFound,
+        format!("Item with key {} not found in the store", item_key),
+    ))
+}
+
+/// Applies `f` to the live item with the given id and writes the result back under the same key.
+/// The previous state is kept as a revision authored by `author`.
+pub(crate) fn modify_item<T>(
+    author: Principal,
+    item_id: &ItemId,
+    f: impl FnOnce(&mut Item) -> Result<T, (ItemUpdateErrorCode, String)>,
+) -> Result<T, (ItemUpdateErrorCode, String)> {
+    modify_item_as(author, item_id, ItemRevisionReason::Patched, f).map(|(res, _)| res)
+}
+
+/// Same as `modify_item`, but records the previous state with the given reason
+/// and also returns the number of that revision.
+pub(crate) fn modify_item_as<T>(
+    author: Principal,
+    item_id: &ItemId,
+    reason: ItemRevisionReason,
+    f: impl FnOnce(&mut Item) -> Result<T, (ItemUpdateErrorCode, String)>,
+) -> Result<(T, RevisionNumber), (ItemUpdateErrorCode, String)> {
+    let item_key = ITEMS_IN_ID.with(|p| p.borrow().get(item_id)).ok_or((
+        ItemUpdateErrorCode::ItemNotFound,
+        format!("Item with id {} not found in the store", item_id),
+    ))?;
+
+    let prev = ITEMS.with(|p| p.borrow().get(&item_key)).ok_or((
+        ItemUpdateErrorCode::ItemNotFound,
+        format!("Item with key {} not found in the store", item_key),
+    ))?;
+
+    let mut item = prev.clone();
+    let res = f(&mut item)?;
+
+    if item.id != prev.id {
+        return Err((
+            ItemUpdateErrorCode::InvalidItem,
+            format!("Item id cannot be changed from {}", prev.id),
+        ));
+    }
+
+    filter::reindex_item(&prev, &item);
+    let revision = revision::record_revision(prev, author, reason);
+    ITEMS.with_borrow_mut(|p| p.insert(item_key, item));
+
+    Ok((res, revision))
+}
+
+/// Converts a V1 item to V2 in place.
+/// Unused trailing V1 dimension slots are dropped and every key is kept,
+/// so keys stored elsewhere still address the same variants.
+pub(crate) fn migrate_item_to_v2(
+    author: Principal,
+    item_id: &ItemId,
+) -> Result<(), (ItemUpdateErrorCode, String)> {
+    let author = auth::require_staff::<ItemUpdateErrorCode>(author)?;
+
+    let dimensions = modify_item(author, item_id, |item| {
+        item.version = item
+            .version
+            .clone()
+            .try_into_v2()
+            .map_err(|message| (ItemUpdateErrorCode::InvalidItem, message))?;
+        Ok(item.dimensions())
+    })?;
+    relation::normalize_relation_keys(item_id, dimensions);
+
+    Ok(())
+}
+
+pub(crate) fn update_item_gallery(
+    author: Principal,
+    item_id: &ItemId,
+    key: ImageVecKey,
+    op: GalleryOperation,
+) -> Result<Vec<ImageKey>, (ItemUpdateErrorCode, String)> {
+    let author = auth::require_staff::<ItemUpdateErrorCode>(author)?;
+
+    modify_item(author, item_id, |item| {
+        item.images_mut()
+            .apply_gallery_operation(key, op)
+            .map_err(|message| (ItemUpdateErrorCode::InvalidGallery, message))
+    })
+}
+
+pub(crate) fn patch_item(
+    author: Principal,
+    item_id: &ItemId,
+    patch: ItemPatch,
+) -> Result<(), (ItemUpdateErrorCode, String)> {
+    let author = auth::require_staff::<ItemUpdateErrorCode>(author)?;
+
+    modify_item(author, item_id, |item| {
+        patch.apply(item);
+        Ok(())
+    })
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct ItemPageRequestV2 {
+    pub item_id: ItemId,
+    pub attr: AttrRequestV2,
+    pub currency: Currency,
+    // Image renditions to return alongside the images
+    pub rendition: Option<RenditionProfile>,
+    // Preferred locales of the content, most preferred first
+    pub locales: Option<Vec<Locale>>,
+    // Unit system to show typed spec values in, instead of the units they were given in
+    pub unit_system: Option<UnitSystem>,
+    // Whether to return summaries of the related items
+    pub include_related: Option<bool>,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct AttrRequestV2 {
+    pub keys: AttrKeysV2,
+    pub changed_key_index: Option<u8>,
+    // Whether to return the availability of every variant
+    pub include_availability: Option<bool>,
+}
+
+impl From<&ItemPageRequestToStoreCanister> for ItemPageRequestV2 {
+    fn from(arg: &ItemPageRequestToStoreCanister) -> Self {
+        Self {
+            item_id: arg.item_id,
+            attr: AttrRequestV2 {
+                keys: (&arg.attr.keys).into(),
+                changed_key_index: arg.attr.changed_key_index,
+                include_availability: None,
+            },
+            currency: arg.currency.clone(),
+            rendition: None,
+            locales: None,
+            unit_system: None,
+            include_related: None,
+        }
+    }
+}
+
+/// Page response that works for items of any version and any number of dimensions.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct ItemPageResponseV2 {
+    pub static_data: Option<ItemPageStaticDataV2>,
+    pub price: Price,
+    pub images: Vec<MediaDataWithCaption>,
+    pub stock: Stock,
+    pub attr_status: AttrStatusesResponseV2,
+    pub specs: Option<SpecResponse>,
+    // One flag per label of `specs`, set when the value is not the same for every variant
+    pub specs_differ: Option<Vec<Vec<bool>>>,
+    pub fallback_attr: Option<AttrKeysV2>,
+    pub availability: Option<VariantAvailabilityMatrix>,
+    // One entry per image in `images`, when a rendition profile was requested
+    // and the store has rendition settings
+    pub image_renditions: Option<Vec<Vec<MediaRendition>>>,
+    // Kind of each entry in `images`
+    pub media_kinds: Vec<MediaKind>,
+    // Items related to the item and to the variant, when requested
+    pub related: Option<Vec<RelatedItem>>,
+    // Components of the item when it is a bundle
+    pub bundle_components: Option<Vec<BundleComponentResponse>>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct ItemPageStaticDataV2 {
+    pub item_name: ItemName,
+    pub descriptions: Vec<String>,
+    pub tags: Vec<Tag>,
+    pub attrs: AttrIndexesResponseV2,
+    pub store_name: StoreName,
+    // Locale of the name and descriptions, `None` when the store has no default locale
+    // and the item has no translation for the requested locales
+    pub locale: Option<Locale>,
+    // Ratings of the approved reviews, `None` when the item has none
+    pub rating: Option<RatingSummary>,
+}
+
 /// Fetches the page information for a specific item.
+///
+/// Only the first four dimensions can be addressed through this request,
+/// so items with more dimensions should be fetched with `get_item_page_data_v2`.
 pub(crate) fn get_item_page_data(
     arg: &ItemPageRequestToStoreCanister,
 ) -> Result<ItemPageResponseFromStoreCanister, (ItemPageFromStoreErrorCode, String)> {
-    let mut static_data: Option<ItemPageStaticDataFromStoreCanister> = None;
+    let res = get_item_page_data_v2(&arg.into())?;
+
+    let fallback_attr = match res.fallback_attr {
+        Some(keys) => Some(keys.normalized(V1_DIMENSIONS).to_v1().ok_or((
+            ItemPageFromStoreErrorCode::NoAvailableAttr,
+            format!(
+                "Fallback attribute {:?} of item with id {} cannot be expressed with {} dimensions",
+                keys, arg.item_id, V1_DIMENSIONS
+            ),
+        ))?),
+        None => None,
+    };
+
+    let static_data = res
+        .static_data
+        .map(|data| ItemPageStaticDataFromStoreCanister {
+            item_name: data.item_name,
+            descriptions: data.descriptions,
+            tags: data.tags,
+            attrs: to_v1_indexes_response(data.attrs),
+            store_name: data.store_name,
+        });
+
+    let res = ItemPageResponseFromStoreCanister {
+        static_data,
+        price: res.price,
+        images: res.images,
+        stock: res.stock,
+        attr_status: to_v1_statuses_response(res.attr_status),
+        specs: res.specs,
+        fallback_attr,
+    };
+
+    Ok(res)
+}
+
+/// Fetches the page information for a specific item with any number of dimensions.
+pub(crate) fn get_item_page_data_v2(
+    arg: &ItemPageRequestV2,
+) -> Result<ItemPageResponseV2, (ItemPageFromStoreErrorCode, String)> {
+    let mut static_data: Option<ItemPageStaticDataV2> = None;
 
     let item_key = match ITEMS_IN_ID.with(|p| p.borrow().get(&arg.item_id)) {
         Some(item) => item,
@@ -169,7 +683,10 @@ pub(crate) fn get_item_page_data(
         }
     };
 
-    let item_name = item.name.clone();
+    let chain = LocaleChain::for_store(arg.locales.as_deref().unwrap_or_default());
+    let translations = item.translations.clone().unwrap_or_default();
+
+    let item_name = translations.name(&chain).unwrap_or(&item.name).clone();
 
     let store_name = STORE_DATA.with(|p| match p.borrow().get() {
         StoreData::V1(store_data) => store_data.name.clone(),
@@ -177,35 +694,33 @@ pub(crate) fn get_item_page_data(
     });
 
     if arg.attr.changed_key_index.is_none() {
-        match item.version {
-            ItemVersion::V1 {
-                ref attrs,
-                ref descriptions,
-                ref tags,
-                ..
-            } => {
-                let attrs = attrs.get_attrs_indexes_result();
-
-                static_data = Some(ItemPageStaticDataFromStoreCanister {
-                    item_name,
-                    descriptions: descriptions.clone(),
-                    tags: tags.clone(),
-                    attrs,
-                    store_name,
-                });
-            }
-        }
+        let mut attrs = item.get_attrs_indexes_result();
+        translations.translate_attrs(&mut attrs, &chain);
+
+        static_data = Some(ItemPageStaticDataV2 {
+            item_name,
+            descriptions: translations
+                .descriptions(&chain)
+                .unwrap_or(item.descriptions())
+                .clone(),
+            tags: item.tags().clone(),
+            attrs,
+            store_name,
+            locale: chain.resolved_locale(&translations.name),
+            rating: review::get_rating_summary(&item.id),
+        });
     }
 
-    let mut attr_status = item.get_attr_statuses(&arg.attr.keys);
-
-    let (attr_data, fallback_attr) = get_attr_data_or_fallback(&item, arg, &attr_status);
+    let (attr_data, fallback_attr) = get_attr_data_or_fallback(&item, arg);
 
-    if fallback_attr.is_some() {
-        attr_status = item.get_attr_statuses(&fallback_attr.unwrap());
-    }
+    let attr_keys = fallback_attr.as_ref().unwrap_or(&arg.attr.keys);
+    let attr_status = item.get_attr_statuses(attr_keys);
+    let image_renditions = get_image_renditions(&item, attr_keys, arg.rendition.as_ref());
+    let media_kinds = item.get_variant(attr_keys).map_or(Vec::new(), |attr_data| {
+        item.images().get_index_kinds(&attr_data.image_vec_key)
+    });
 
-    let attr_data = match attr_data {
+    let mut attr_data = match attr_data {
         Some(attr_data) => attr_data,
         None => {
             return Err((
@@ -218,58 +733,103 @@ pub(crate) fn get_item_page_data(
         }
     };
 
-    let res = ItemPageResponseFromStoreCanister {
+    if arg.unit_system.is_some() {
+        attr_data.specs = item.get_variant(attr_keys).and_then(|variant| {
+            item.specs()
+                .get_specs_in(&variant.spec_keys, arg.unit_system)
+        });
+    }
+    if let Some(specs) = attr_data.specs.as_mut() {
+        translations.translate_specs(specs, &chain);
+    }
+    let specs_differ = match (&attr_data.specs, item.get_variant(attr_keys)) {
+        (Some(_), Some(variant)) => {
+            let variants = item.variants();
+            let variant_keys: Vec<_> = variants.iter().map(|(_, data)| &data.spec_keys).collect();
+            Some(
+                item.specs()
+                    .mark_differences(&variant.spec_keys, &variant_keys),
+            )
+        }
+        _ => None,
+    };
+
+    let res = ItemPageResponseV2 {
         static_data,
         price: attr_data.price,
         images: attr_data.image_vec,
         stock: attr_data.stock,
         attr_status,
         specs: attr_data.specs,
+        specs_differ,
         fallback_attr,
+        availability: arg
+            .attr
+            .include_availability
+            .unwrap_or(false)
+            .then(|| item.get_availability_matrix(&arg.currency)),
+        image_renditions,
+        media_kinds,
+        related: arg.include_related.unwrap_or(false).then(|| {
+            let attr_keys = attr_keys.normalized(item.dimensions());
+            relation::get_related_items(item.id, &attr_keys, &arg.currency, &chain)
+        }),
+        bundle_components: item
+            .bundle
+            .as_ref()
+            .map(|bundle| bundle.components_response(&chain)),
     };
 
     Ok(res)
 }
 
+fn get_image_renditions(
+    item: &Item,
+    attr_keys: &AttrKeysV2,
+    profile: Option<&RenditionProfile>,
+) -> Option<Vec<Vec<MediaRendition>>> {
+    let profile = profile?;
+    let settings = data::get_store_data()?.rendition?;
+    let attr_data = item.get_variant(attr_keys)?;
+
+    Some(
+        item.images()
+            .get_index_renditions(&attr_data.image_vec_key, &settings, profile),
+    )
+}
+
+/// Returns the data of the requested variant, or of the best fallback
+/// according to the item's `VariantFallbackStrategy` with the keys it was found at.
 pub(crate) fn get_attr_data_or_fallback(
     item: &Item,
-    arg: &ItemPageRequestToStoreCanister,
-    attr_status: &AttrStatusesResponse,
-) -> (Option<AttrSpecificDataResponse>, Option<AttrKeys>) {
+    arg: &ItemPageRequestV2,
+) -> (Option<AttrSpecificDataResponse>, Option<AttrKeysV2>) {
     if let Some(attr_data) = item.get_attr_data(&arg.attr.keys, &arg.currency) {
         return (Some(attr_data), None);
     }
 
-    if let Some(changed_key) = arg.attr.changed_key_index {
-        for (i, attr_status_one) in attr_status.iter().enumerate() {
-            if i == changed_key as usize {
-                continue;
-            }
-            for (j, status) in attr_status_one.iter().enumerate() {
-                if status.is_some() {
-                    let attr_keys = match arg.attr.keys.replace(i, &(j as u8)) {
-                        Ok(attr_keys) => attr_keys,
-                        Err(_) => continue,
-                    };
-                    let attr_data = match item.get_attr_data(&attr_keys, &arg.currency) {
-                        Some(attr_data) => attr_data,
-                        None => continue,
-                    };
-                    return (Some(attr_data), Some(attr_keys));
-                }
-            }
+    let strategy = item.fallback_strategy.clone().unwrap_or_default();
+    let candidates = variant::rank_fallback_candidates(
+        item,
+        &arg.attr.keys,
+        arg.attr.changed_key_index.map(usize::from),
+        &arg.currency,
+        &strategy,
+    );
+
+    for attr_keys in candidates {
+        if let Some(attr_data) = item.get_attr_data(&attr_keys, &arg.currency) {
+            return (Some(attr_data), Some(attr_keys));
         }
     }
 
-    let attr_keys = AttrKeys::default();
-    if let Some(attr_data) = item.get_attr_data(&attr_keys, &arg.currency) {
-        return (Some(attr_data), Some(attr_keys));
-    }
-
     (None, None)
 }
 
-pub(crate) async fn insert_items(vec: Vec<Item>) -> Vec<(ItemId, ItemKey)> {
+/// Inserts items under freshly created keys.
+/// When an item with the same id already exists, its previous state is kept as a revision
+/// naming `author`, which callers take from `auth::require_staff`.
+pub(crate) async fn insert_items(author: Principal, vec: Vec<Item>) -> Vec<(ItemId, ItemKey)> {
     let mut prev_items: Vec<(ItemId, ItemKey)> = Vec::new();
     let mut keys: Vec<ItemKey> = Vec::new();
     let mut ids: Vec<ItemId> = Vec::new();
@@ -297,12 +857,24 @@ pub(crate) async fn insert_items(vec: Vec<Item>) -> Vec<(ItemId, ItemKey)> {
     });
 
     ITEMS_IN_ID.with_borrow_mut(|p| {
-        for (i, key) in keys.into_iter().enumerate() {
-            if let Some(prev_key) = p.insert(ids[i], key) {
+        for (i, key) in keys.iter().enumerate() {
+            if let Some(prev_key) = p.insert(ids[i], *key) {
                 prev_items.push((ids[i], prev_key));
             };
         }
     });
 
+    ITEMS.with_borrow(|p| {
+        for (_, prev_key) in prev_items.iter() {
+            if let Some(prev_item) = p.get(prev_key) {
+                filter::unindex_item(&prev_item);
+                revision::record_revision(prev_item, author, ItemRevisionReason::Replaced);
+            }
+        }
+        keys.iter()
+            .filter_map(|key| p.get(key))
+            .for_each(|item| filter::index_item(&item));
+    });
+
     prev_items
 }
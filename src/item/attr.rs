@@ -2,12 +2,16 @@ use super::{image::ImageVecKey, spec::SpecIndexKey};
 use candid::{CandidType, Deserialize};
 use common::{
     item::{
-        attr::{AttrIndexResponse, AttrIndexesResponse, AttrKey, AttrKeys, AttrType, Stock},
+        attr::{
+            AttrIndexResponse, AttrIndexesResponse, AttrKey, AttrKeys, AttrStatusResponse,
+            AttrStatusesResponse, AttrType, Stock,
+        },
         MediaDataWithCaption,
     },
     unit::{Currency, Price},
 };
-use std::collections::BTreeMap;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
 
 #[derive(CandidType, Deserialize, Debug, Clone)]
 pub struct AttrSpecificData {
@@ -89,6 +93,10 @@ impl AttrIndexes {
     pub fn builder() -> AttrIndexesBuilder {
         AttrIndexesBuilder::default()
     }
+
+    pub fn iter(&self) -> impl Iterator<Item = Option<&AttrIndex>> {
+        self.0.iter().map(|index| index.as_ref())
+    }
 }
 
 #[derive(Default)]
@@ -217,6 +225,19 @@ impl ItemAttrsV1Builder {
         self
     }
 
+    /// Adds a copy of `template` for every combination of the indexes that has no data yet.
+    /// Call this after `indexes` and after any `attr` calls for variants that differ from the template.
+    pub fn fill_matrix(mut self, template: &AttrSpecificData) -> Self {
+        let indexes: Vec<Option<&AttrIndex>> = self.indexes.iter().collect();
+
+        for keys in attr_key_combinations(&indexes) {
+            if let Some(keys) = keys.to_v1() {
+                self.map.entry(keys).or_insert_with(|| template.clone());
+            }
+        }
+        self
+    }
+
     pub fn build(self) -> ItemAttrsV1 {
         ItemAttrsV1 {
             map: self.map,
@@ -224,3 +245,306 @@ impl ItemAttrsV1Builder {
         }
     }
 }
+
+/// Number of dimensions addressed by `AttrKeys` and `ItemAttrsV1`.
+pub const V1_DIMENSIONS: usize = 4;
+
+/// Attribute keys with one key per dimension and no fixed dimension count.
+#[derive(
+    CandidType, Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord,
+)]
+pub struct AttrKeysV2(pub Vec<AttrKey>);
+
+impl AttrKeysV2 {
+    /// Returns the key of the dimension, treating missing trailing keys as the default key.
+    pub fn get(&self, index: usize) -> AttrKey {
+        self.0.get(index).copied().unwrap_or_default()
+    }
+
+    pub fn replace(&self, index: usize, key: AttrKey) -> Self {
+        let mut keys = self.normalized(self.0.len().max(index + 1));
+        keys.0[index] = key;
+        keys
+    }
+
+    /// Whether every key beyond the first `dimensions` is the default key,
+    /// so that `normalized` drops nothing.
+    pub fn fits(&self, dimensions: usize) -> bool {
+        self.0
+            .iter()
+            .skip(dimensions)
+            .all(|key| *key == AttrKey::default())
+    }
+
+    /// Pads with default keys or truncates so that there is exactly one key per dimension.
+    pub fn normalized(&self, dimensions: usize) -> Self {
+        let mut keys = self.0.clone();
+        keys.resize(dimensions, AttrKey::default());
+        Self(keys)
+    }
+
+    pub fn to_v1(&self) -> Option<AttrKeys> {
+        let mut keys = AttrKeys::default();
+        for (i, key) in self.0.iter().enumerate() {
+            keys = keys.replace(i, key).ok()?;
+        }
+        Some(keys)
+    }
+}
+
+impl From<&AttrKeys> for AttrKeysV2 {
+    fn from(keys: &AttrKeys) -> Self {
+        Self(keys.0.to_vec())
+    }
+}
+
+/// One entry per dimension. `ItemAttrsV1` always has four entries, some of which may be `None`.
+pub type AttrIndexesResponseV2 = Vec<Option<AttrIndexResponse>>;
+
+/// One entry per dimension, listing the status of each value of that dimension.
+pub type AttrStatusesResponseV2 = Vec<Vec<Option<AttrStatusResponse>>>;
+
+pub fn to_v1_indexes_response(indexes: AttrIndexesResponseV2) -> AttrIndexesResponse {
+    let mut iter = indexes.into_iter();
+    std::array::from_fn(|_| iter.next().flatten())
+}
+
+pub fn to_v1_statuses_response(statuses: AttrStatusesResponseV2) -> AttrStatusesResponse {
+    let mut iter = statuses.into_iter();
+    std::array::from_fn(|_| iter.next().unwrap_or_default())
+}
+
+/// Lists every combination of the keys of the given dimensions, in key order.
+/// Unused dimension slots keep the default key.
+pub fn attr_key_combinations(indexes: &[Option<&AttrIndex>]) -> Vec<AttrKeysV2> {
+    let mut combinations = vec![AttrKeysV2(Vec::with_capacity(indexes.len()))];
+
+    for index in indexes {
+        let keys: Vec<AttrKey> = match index {
+            Some(index) => index.map.keys().copied().collect(),
+            None => vec![AttrKey::default()],
+        };
+
+        combinations = combinations
+            .iter()
+            .flat_map(|prefix| {
+                keys.iter().map(move |key| {
+                    let mut next = prefix.clone();
+                    next.0.push(*key);
+                    next
+                })
+            })
+            .collect();
+    }
+
+    combinations
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct ItemAttrsV2 {
+    pub map: BTreeMap<AttrKeysV2, AttrSpecificData>,
+    pub indexes: Vec<AttrIndex>,
+}
+
+impl ItemAttrsV2 {
+    pub fn builder() -> ItemAttrsV2Builder {
+        ItemAttrsV2Builder::default()
+    }
+
+    pub fn get_attrs(&self, keys: &AttrKeysV2) -> Option<AttrSpecificData> {
+        let data = self.map.get(&keys.normalized(self.indexes.len()))?.clone();
+        Some(data)
+    }
+
+    pub fn get_is_in_stock(&self, attr_keys: &AttrKeysV2) -> Option<bool> {
+        let attr_data = self.map.get(&attr_keys.normalized(self.indexes.len()))?;
+        Some(attr_data.stock > 0)
+    }
+
+    pub fn get_attrs_indexes_result(&self) -> AttrIndexesResponseV2 {
+        self.indexes
+            .iter()
+            .map(|index| {
+                Some(AttrIndexResponse {
+                    name: index.name.clone(),
+                    value: index.map.values().cloned().collect(),
+                })
+            })
+            .collect()
+    }
+
+    pub fn get_attrs_index_values(&self, attr_keys: &AttrKeysV2) -> Vec<Option<String>> {
+        self.indexes
+            .iter()
+            .enumerate()
+            .map(|(i, index)| {
+                index.map.get(&attr_keys.get(i)).map(|v| match v {
+                    AttrType::Text(t) => t.clone(),
+                    AttrType::Color(c) => c.name.clone(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Migrates V1 attributes by dropping the unused trailing dimension slots.
+/// Keys are kept as they are, so V1 attributes with an unused slot before a used one
+/// cannot be migrated.
+impl TryFrom<ItemAttrsV1> for ItemAttrsV2 {
+    type Error = String;
+
+    fn try_from(attrs: ItemAttrsV1) -> Result<Self, Self::Error> {
+        let dimensions = attrs
+            .indexes
+            .0
+            .iter()
+            .rposition(|index| index.is_some())
+            .map_or(0, |i| i + 1);
+
+        if let Some(i) = attrs.indexes.0[..dimensions]
+            .iter()
+            .position(|index| index.is_none())
+        {
+            return Err(format!(
+                "Dimension {} is unused while a later one is used, so keys cannot be kept",
+                i
+            ));
+        }
+
+        let mut map = BTreeMap::new();
+        for (keys, data) in attrs.map {
+            let keys = AttrKeysV2::from(&keys);
+            if !keys.fits(dimensions) {
+                return Err(format!(
+                    "Keys {:?} use a dimension that has no definition",
+                    keys
+                ));
+            }
+            map.insert(keys.normalized(dimensions), data);
+        }
+
+        Ok(ItemAttrsV2 {
+            map,
+            indexes: attrs.indexes.0.into_iter().flatten().collect(),
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct ItemAttrsV2Builder {
+    pub map: BTreeMap<AttrKeysV2, AttrSpecificData>,
+    pub indexes: Vec<AttrIndex>,
+}
+
+impl ItemAttrsV2Builder {
+    pub fn attr(mut self, keys: Vec<AttrKey>, data: AttrSpecificData) -> Self {
+        self.map.insert(AttrKeysV2(keys), data);
+        self
+    }
+
+    /// Appends a dimension. Dimensions are addressed in the order they are added.
+    pub fn index(mut self, index: AttrIndex) -> Self {
+        self.indexes.push(index);
+        self
+    }
+
+    /// Adds a copy of `template` for every combination of the indexes that has no data yet.
+    /// Call this after all `index` calls.
+    pub fn fill_matrix(mut self, template: &AttrSpecificData) -> Self {
+        let dimensions = self.indexes.len();
+        let existing: BTreeSet<AttrKeysV2> = self
+            .map
+            .keys()
+            .map(|keys| keys.normalized(dimensions))
+            .collect();
+
+        let indexes: Vec<Option<&AttrIndex>> = self.indexes.iter().map(Some).collect();
+        for keys in attr_key_combinations(&indexes) {
+            if !existing.contains(&keys) {
+                self.map.insert(keys, template.clone());
+            }
+        }
+        self
+    }
+
+    /// Normalizes every key to the number of dimensions added.
+    /// Fails when keys use a dimension that was not added, or when two keys refer to the same
+    /// variant, so that no variant is dropped silently.
+    pub fn build(self) -> Result<ItemAttrsV2, String> {
+        let dimensions = self.indexes.len();
+
+        let mut map = BTreeMap::new();
+        for (keys, data) in self.map {
+            if !keys.fits(dimensions) {
+                return Err(format!(
+                    "Keys {:?} use a dimension beyond the {} defined",
+                    keys, dimensions
+                ));
+            }
+            if map.insert(keys.normalized(dimensions), data).is_some() {
+                return Err(format!(
+                    "Keys {:?} refer to the same variant as other keys",
+                    keys
+                ));
+            }
+        }
+
+        Ok(ItemAttrsV2 {
+            map,
+            indexes: self.indexes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builder() -> ItemAttrsV2Builder {
+        ItemAttrsV2::builder()
+            .index(
+                AttrIndex::builder("color")
+                    .label(0, AttrType::Text("red".to_string()))
+                    .label(1, AttrType::Text("blue".to_string()))
+                    .build(),
+            )
+            .attr(vec![0], AttrSpecificData::builder().stock(1).build())
+    }
+
+    #[test]
+    fn keys_beyond_the_dimensions_are_rejected() {
+        let attrs = builder()
+            .attr(vec![1, 1], AttrSpecificData::builder().stock(2).build())
+            .build();
+
+        assert!(attrs.is_err());
+    }
+
+    #[test]
+    fn keys_of_the_same_variant_are_rejected() {
+        let attrs = builder()
+            .attr(vec![0, 0], AttrSpecificData::builder().stock(2).build())
+            .build();
+
+        assert!(attrs.is_err());
+    }
+
+    #[test]
+    fn fill_matrix_keeps_variants_given_with_short_keys() {
+        let attrs = builder()
+            .index(
+                AttrIndex::builder("size")
+                    .label(0, AttrType::Text("S".to_string()))
+                    .build(),
+            )
+            .fill_matrix(&AttrSpecificData::builder().build())
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            attrs.map.keys().cloned().collect::<Vec<_>>(),
+            vec![AttrKeysV2(vec![0, 0]), AttrKeysV2(vec![1, 0])]
+        );
+        assert_eq!(attrs.map[&AttrKeysV2(vec![0, 0])].stock, 1);
+    }
+}
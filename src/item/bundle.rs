@@ -0,0 +1,340 @@
+use super::{
+    attr::{AttrKeysV2, AttrSpecificData},
+    get_item, modify_item, Item, ItemId, ItemName,
+};
+use crate::{
+    auth::{self, AuthErrorCode},
+    locale::LocaleChain,
+    ITEMS, ITEMS_IN_ID,
+};
+use candid::{CandidType, Deserialize, Principal};
+use common::{
+    item::{attr::Stock, spec::SpecResponse, MediaDataWithCaption},
+    unit::{Currency, Price},
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Largest number of components in a bundle.
+pub const MAX_BUNDLE_COMPONENTS: usize = 20;
+
+/// A variant of another item sold as part of a bundle.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct BundleComponent {
+    pub item_id: ItemId,
+    pub attr_keys: AttrKeysV2,
+    pub quantity: Stock,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub enum BundlePricing {
+    // The price of the variant of the bundle item itself
+    Fixed,
+    // The sum of the component prices, less a percentage
+    DiscountedSum { percent_off: f64 },
+}
+
+/// Makes an item a bundle of variants of other items.
+///
+/// The stock of a bundle is derived from its components, so the stock of
+/// the variants of the bundle item itself is not used.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ItemBundle {
+    pub components: Vec<BundleComponent>,
+    pub pricing: BundlePricing,
+}
+
+/// A component as shown on the page of its bundle.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct BundleComponentResponse {
+    pub item_id: ItemId,
+    pub attr_keys: AttrKeysV2,
+    pub quantity: Stock,
+    pub item_name: ItemName,
+    // Label of the variant in each dimension of its item
+    pub attr_values: Vec<Option<String>>,
+    pub images: Vec<MediaDataWithCaption>,
+    pub specs: Option<SpecResponse>,
+    pub stock: Stock,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum BundleErrorCode {
+    Unauthorized,
+    ItemNotFound,
+    NotABundle,
+    ComponentNotFound,
+    InvalidBundle,
+    InsufficientStock,
+}
+
+impl From<AuthErrorCode> for BundleErrorCode {
+    fn from(code: AuthErrorCode) -> Self {
+        match code {
+            AuthErrorCode::Unauthorized => BundleErrorCode::Unauthorized,
+        }
+    }
+}
+
+impl ItemBundle {
+    /// Number of bundles the stock of the components allows, the minimum across them.
+    /// A component that no longer exists leaves the bundle out of stock.
+    pub fn stock(&self) -> Stock {
+        self.components
+            .iter()
+            .map(|component| {
+                get_item(&component.item_id)
+                    .ok()
+                    .and_then(|item| {
+                        item.get_variant(&component.attr_keys)
+                            .map(|data| data.stock / component.quantity)
+                    })
+                    .unwrap_or(0)
+            })
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// The price derived from the components, or `None` when the pricing is fixed
+    /// or a component has no price in `currency`.
+    pub fn price(&self, currency: &Currency) -> Option<Price> {
+        let BundlePricing::DiscountedSum { percent_off } = self.pricing else {
+            return None;
+        };
+
+        let mut sum = 0.0;
+        for component in &self.components {
+            let item = get_item(&component.item_id).ok()?;
+            let price = item
+                .get_variant(&component.attr_keys)?
+                .price
+                .get(currency)?;
+            sum += price.value() * component.quantity as f64;
+        }
+
+        Some(Price::new(sum * (100.0 - percent_off) / 100.0))
+    }
+
+    pub fn components_response(&self, chain: &LocaleChain) -> Vec<BundleComponentResponse> {
+        self.components
+            .iter()
+            .filter_map(|component| {
+                let item = get_item(&component.item_id).ok()?;
+                let data = item.get_variant(&component.attr_keys)?;
+                let translations = item.translations.clone().unwrap_or_default();
+                let mut specs = item.specs().get_specs(&data.spec_keys);
+                if let Some(specs) = specs.as_mut() {
+                    translations.translate_specs(specs, chain);
+                }
+
+                Some(BundleComponentResponse {
+                    item_id: component.item_id,
+                    attr_keys: component.attr_keys.clone(),
+                    quantity: component.quantity,
+                    item_name: translations.name(chain).unwrap_or(&item.name).clone(),
+                    attr_values: item.get_attrs_index_values(&component.attr_keys),
+                    images: item
+                        .images()
+                        .get_index_vec(&data.image_vec_key)
+                        .unwrap_or_default(),
+                    specs,
+                    stock: data.stock,
+                })
+            })
+            .collect()
+    }
+}
+
+impl Item {
+    /// Stock of a variant as sold. The stock of a bundle comes from its components,
+    /// so the stock of its own variants is not used.
+    pub fn effective_stock(&self, data: &AttrSpecificData) -> Stock {
+        match &self.bundle {
+            Some(bundle) => bundle.stock(),
+            None => data.stock,
+        }
+    }
+
+    /// Price of a variant in `currency` as sold. A bundle priced from its components
+    /// uses their discounted sum, and otherwise the price of its own variant.
+    pub fn effective_price(&self, data: &AttrSpecificData, currency: &Currency) -> Option<Price> {
+        self.bundle
+            .as_ref()
+            .and_then(|bundle| bundle.price(currency))
+            .or_else(|| data.price.get(currency).copied())
+    }
+}
+
+fn invalid(message: String) -> Result<(), (BundleErrorCode, String)> {
+    Err((BundleErrorCode::InvalidBundle, message))
+}
+
+/// Returns the first live bundle that has a variant of the item as a component.
+fn containing_bundle(item_id: &ItemId) -> Option<ItemId> {
+    ITEMS_IN_ID.with_borrow(|ids| {
+        ITEMS.with_borrow(|items| {
+            ids.iter().find_map(|(bundle_id, key)| {
+                items
+                    .get(&key)
+                    .and_then(|item| item.bundle)
+                    .filter(|bundle| bundle.components.iter().any(|c| c.item_id == *item_id))
+                    .map(|_| bundle_id)
+            })
+        })
+    })
+}
+
+/// Checks that the item is not a component of another bundle, and that every component is
+/// an existing variant of another item that is not a bundle itself. Normalizes the keys of the
+/// components to the dimensions of their items.
+fn validate_bundle(
+    item_id: &ItemId,
+    bundle: &mut ItemBundle,
+) -> Result<(), (BundleErrorCode, String)> {
+    if let Some(bundle_id) = containing_bundle(item_id) {
+        return invalid(format!(
+            "Item with id {} is a component of bundle with id {} and cannot be a bundle",
+            item_id, bundle_id
+        ));
+    }
+    if bundle.components.is_empty() {
+        return invalid("A bundle needs at least one component".to_string());
+    }
+    if bundle.components.len() > MAX_BUNDLE_COMPONENTS {
+        return invalid(format!(
+            "A bundle can have at most {} components",
+            MAX_BUNDLE_COMPONENTS
+        ));
+    }
+    if let BundlePricing::DiscountedSum { percent_off } = bundle.pricing {
+        if !(0.0..=100.0).contains(&percent_off) {
+            return invalid(format!("Discount of {}% is out of range", percent_off));
+        }
+    }
+
+    for component in bundle.components.iter_mut() {
+        if component.item_id == *item_id {
+            return invalid(format!("Item with id {} cannot contain itself", item_id));
+        }
+        if component.quantity == 0 {
+            return invalid(format!(
+                "Quantity of item with id {} must be positive",
+                component.item_id
+            ));
+        }
+
+        let item = get_item(&component.item_id)
+            .map_err(|(_, message)| (BundleErrorCode::ComponentNotFound, message))?;
+        if item.bundle.is_some() {
+            return invalid(format!(
+                "Item with id {} is a bundle and cannot be a component",
+                component.item_id
+            ));
+        }
+        component.attr_keys = component.attr_keys.normalized(item.dimensions());
+        if item.get_variant(&component.attr_keys).is_none() {
+            return Err((
+                BundleErrorCode::ComponentNotFound,
+                format!(
+                    "Attribute with keys {:?} of item with id {} not found in the store",
+                    component.attr_keys, component.item_id
+                ),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Makes an item a bundle, or a regular item again when `bundle` is `None`.
+pub(crate) fn set_item_bundle(
+    caller: Principal,
+    item_id: &ItemId,
+    bundle: Option<ItemBundle>,
+) -> Result<(), (BundleErrorCode, String)> {
+    let caller = auth::require_staff::<BundleErrorCode>(caller)?;
+
+    let bundle = match bundle {
+        Some(mut bundle) => {
+            validate_bundle(item_id, &mut bundle)?;
+            Some(bundle)
+        }
+        None => None,
+    };
+
+    modify_item(caller, item_id, |item| {
+        item.bundle = bundle;
+        Ok(())
+    })
+    .map_err(|(_, message)| (BundleErrorCode::ItemNotFound, message))
+}
+
+/// Sells `count` bundles, taking the stock of every component at once.
+/// Nothing is changed unless every component has enough stock.
+///
+/// Returns the stock of the bundle left afterwards.
+pub(crate) fn sell_bundle(
+    caller: Principal,
+    item_id: &ItemId,
+    count: Stock,
+) -> Result<Stock, (BundleErrorCode, String)> {
+    let caller = auth::require_staff::<BundleErrorCode>(caller)?;
+
+    let bundle = get_item(item_id)
+        .map_err(|(_, message)| (BundleErrorCode::ItemNotFound, message))?
+        .bundle
+        .ok_or((
+            BundleErrorCode::NotABundle,
+            format!("Item with id {} is not a bundle", item_id),
+        ))?;
+
+    // Components may share an item, so the stock taken is summed per item first
+    let mut taken: BTreeMap<ItemId, BTreeMap<AttrKeysV2, Stock>> = BTreeMap::new();
+    for component in &bundle.components {
+        let amount = component.quantity.checked_mul(count).ok_or((
+            BundleErrorCode::InsufficientStock,
+            format!("Cannot sell {} bundles of item with id {}", count, item_id),
+        ))?;
+        *taken
+            .entry(component.item_id)
+            .or_default()
+            .entry(component.attr_keys.clone())
+            .or_default() += amount;
+    }
+
+    let mut items: Vec<Item> = Vec::new();
+    for (component_id, variants) in &taken {
+        let mut item = get_item(component_id)
+            .map_err(|(_, message)| (BundleErrorCode::ComponentNotFound, message))?;
+        for (attr_keys, amount) in variants {
+            let missing = (
+                BundleErrorCode::ComponentNotFound,
+                format!(
+                    "Attribute with keys {:?} of item with id {} not found in the store",
+                    attr_keys, component_id
+                ),
+            );
+            let mut data = item.get_variant(attr_keys).ok_or(missing)?.clone();
+            data.stock = data.stock.checked_sub(*amount).ok_or((
+                BundleErrorCode::InsufficientStock,
+                format!(
+                    "Item with id {} has {} left of attribute with keys {:?}, {} needed",
+                    component_id, data.stock, attr_keys, amount
+                ),
+            ))?;
+            item.insert_variant(attr_keys, data);
+        }
+        items.push(item);
+    }
+
+    for item in items {
+        let id = item.id;
+        modify_item(caller, &id, |live| {
+            *live = item;
+            Ok(())
+        })
+        .map_err(|(_, message)| (BundleErrorCode::ComponentNotFound, message))?;
+    }
+
+    Ok(bundle.stock())
+}
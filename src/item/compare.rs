@@ -0,0 +1,135 @@
+use super::{attr::AttrKeysV2, spec::unit::UnitSystem, ItemId, ItemName};
+use crate::locale::{Locale, LocaleChain};
+use candid::{CandidType, Deserialize};
+use common::{
+    item::{attr::Stock, spec::SpecValue},
+    unit::{Currency, Price},
+};
+use serde::Serialize;
+
+/// Largest number of variants compared at once.
+pub const MAX_COMPARED_VARIANTS: usize = 10;
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct CompareItemsRequest {
+    pub variants: Vec<(ItemId, AttrKeysV2)>,
+    pub currency: Currency,
+    pub unit_system: Option<UnitSystem>,
+    pub locales: Option<Vec<Locale>>,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum CompareErrorCode {
+    TooManyVariants,
+    ItemNotFound,
+    VariantNotFound,
+}
+
+/// Specs of several variants aligned side by side.
+/// Every row has one cell per column, in the order the variants were requested.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Default)]
+pub struct ComparisonTable {
+    pub columns: Vec<ComparisonColumn>,
+    // `None` when the variant has no price in the requested currency
+    pub price: Vec<Option<Price>>,
+    pub stock: Vec<Stock>,
+    pub rows: Vec<ComparisonRow>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct ComparisonColumn {
+    pub item_id: ItemId,
+    pub attr_keys: AttrKeysV2,
+    pub item_name: ItemName,
+    // Label of the variant in each dimension of its item
+    pub attr_values: Vec<Option<String>>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct ComparisonRow {
+    pub category_name: String,
+    pub label_name: String,
+    pub cells: Vec<ComparisonCell>,
+    // Whether the cells are not all the same
+    pub differs: bool,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub enum ComparisonCell {
+    Value(SpecValue),
+    NotSpecified,
+}
+
+/// Builds a comparison table of the given variants.
+///
+/// Rows are listed in the order their category and label first appear among the columns.
+pub(crate) fn compare_items(
+    arg: &CompareItemsRequest,
+) -> Result<ComparisonTable, (CompareErrorCode, String)> {
+    if arg.variants.len() > MAX_COMPARED_VARIANTS {
+        return Err((
+            CompareErrorCode::TooManyVariants,
+            format!("At most {} variants can be compared", MAX_COMPARED_VARIANTS),
+        ));
+    }
+
+    let chain = LocaleChain::for_store(arg.locales.as_deref().unwrap_or_default());
+    let column_count = arg.variants.len();
+    let mut table = ComparisonTable::default();
+
+    for (column, (item_id, attr_keys)) in arg.variants.iter().enumerate() {
+        let item = super::get_item(item_id)
+            .map_err(|(_, message)| (CompareErrorCode::ItemNotFound, message))?;
+        let attr_keys = attr_keys.normalized(item.dimensions());
+        let variant = item.get_variant(&attr_keys).ok_or((
+            CompareErrorCode::VariantNotFound,
+            format!(
+                "Attribute with keys {:?} of item with id {} not found in the store",
+                attr_keys, item_id
+            ),
+        ))?;
+
+        let translations = item.translations.clone().unwrap_or_default();
+        let mut specs = item
+            .specs()
+            .get_specs_in(&variant.spec_keys, arg.unit_system)
+            .unwrap_or_default();
+        translations.translate_specs(&mut specs, &chain);
+
+        for category in specs {
+            for label in category.label_vec {
+                let position = table.rows.iter().position(|row| {
+                    row.category_name == category.category_name
+                        && row.label_name == label.label_name
+                });
+                let position = position.unwrap_or_else(|| {
+                    table.rows.push(ComparisonRow {
+                        category_name: category.category_name.clone(),
+                        label_name: label.label_name.clone(),
+                        cells: vec![ComparisonCell::NotSpecified; column_count],
+                        differs: false,
+                    });
+                    table.rows.len() - 1
+                });
+                table.rows[position].cells[column] = ComparisonCell::Value(label.value);
+            }
+        }
+
+        table
+            .price
+            .push(item.effective_price(variant, &arg.currency));
+        table.stock.push(item.effective_stock(variant));
+        table.columns.push(ComparisonColumn {
+            item_id: *item_id,
+            item_name: translations.name(&chain).unwrap_or(&item.name).clone(),
+            attr_values: item.get_attrs_index_values(&attr_keys),
+            attr_keys,
+        });
+    }
+
+    for row in table.rows.iter_mut() {
+        row.differs = row.cells.windows(2).any(|pair| pair[0] != pair[1]);
+    }
+
+    Ok(table)
+}
@@ -0,0 +1,363 @@
+use super::{
+    attr::AttrKeysV2,
+    spec::unit::{Unit, UnitDimension},
+    Item, ItemId,
+};
+use crate::{
+    auth::{self, AuthErrorCode},
+    ITEMS, ITEMS_IN_ID, SPEC_VALUE_INDEX,
+};
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{storable::Bound, Storable};
+use serde::Serialize;
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
+};
+
+/// Largest number of items returned by one filter query.
+pub const MAX_FILTER_LIMIT: u64 = 100;
+
+/// Key of a spec value in the index. Entries of the same category and label are adjacent,
+/// and `variant` sorts `None` first, so the prefix of a label starts at `variant: None`.
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SpecValueIndexKey {
+    pub category: String,
+    pub label: String,
+    pub value: String,
+    pub variant: Option<(ItemId, AttrKeysV2)>,
+}
+
+impl Storable for SpecValueIndexKey {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Numeric form of an indexed value, for typed values that have one.
+#[derive(CandidType, Deserialize, Debug, Clone, Default)]
+pub struct SpecValueIndexEntry {
+    // Value in the base unit of its dimension
+    pub base_value: Option<f64>,
+    pub dimension: Option<UnitDimension>,
+}
+
+impl Storable for SpecValueIndexEntry {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumberComparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+/// Condition on the value of a spec label.
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub enum SpecConstraint {
+    Equals(String),
+    OneOf(Vec<String>),
+    // Matches typed values only. With a unit, values of other dimensions never match.
+    Number {
+        comparison: NumberComparison,
+        value: f64,
+        unit: Option<Unit>,
+    },
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct SpecFilter {
+    pub category: String,
+    pub label: String,
+    pub constraint: SpecConstraint,
+}
+
+/// A spec label to count the values of among the matching items.
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct SpecFacetRequest {
+    pub category: String,
+    pub label: String,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct SpecFilterRequest {
+    // A variant matches when it satisfies every filter
+    pub filters: Vec<SpecFilter>,
+    pub facets: Vec<SpecFacetRequest>,
+    pub offset: u64,
+    pub limit: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct SpecFilterMatch {
+    pub item_id: ItemId,
+    pub variants: Vec<AttrKeysV2>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct SpecFacet {
+    pub category: String,
+    pub label: String,
+    // Number of matching items with each value
+    pub values: Vec<(String, u64)>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Default)]
+pub struct SpecFilterResponse {
+    pub items: Vec<SpecFilterMatch>,
+    pub total_items: u64,
+    pub facets: Vec<SpecFacet>,
+}
+
+type Variant = (ItemId, AttrKeysV2);
+
+fn index_entries(item: &Item) -> Vec<(SpecValueIndexKey, SpecValueIndexEntry)> {
+    let specs = item.specs();
+    let mut result = Vec::new();
+
+    for (attr_keys, data) in item.variants() {
+        for entry in specs.get_spec_entries(&data.spec_keys) {
+            let typed = entry.typed.as_ref();
+            result.push((
+                SpecValueIndexKey {
+                    category: entry.category_name,
+                    label: entry.label_name,
+                    value: entry.value,
+                    variant: Some((item.id, attr_keys.clone())),
+                },
+                SpecValueIndexEntry {
+                    base_value: typed.and_then(|typed| typed.base_value()),
+                    dimension: typed
+                        .and_then(|typed| typed.unit())
+                        .map(|unit| unit.dimension()),
+                },
+            ));
+        }
+    }
+
+    result
+}
+
+/// Adds the spec values of every variant of a live item to the index.
+pub(crate) fn index_item(item: &Item) {
+    SPEC_VALUE_INDEX.with_borrow_mut(|p| {
+        for (key, entry) in index_entries(item) {
+            p.insert(key, entry);
+        }
+    });
+}
+
+/// Removes the spec values of an item that is no longer live from the index.
+pub(crate) fn unindex_item(item: &Item) {
+    SPEC_VALUE_INDEX.with_borrow_mut(|p| {
+        for (key, _) in index_entries(item) {
+            p.remove(&key);
+        }
+    });
+}
+
+/// Replaces the indexed spec values of `prev` with those of `item`.
+pub(crate) fn reindex_item(prev: &Item, item: &Item) {
+    unindex_item(prev);
+    index_item(item);
+}
+
+/// Lists the live items, skipping previous versions kept under other keys.
+pub(crate) fn live_items() -> Vec<Item> {
+    let keys: Vec<_> = ITEMS_IN_ID.with_borrow(|p| p.iter().map(|(_, key)| key).collect());
+    ITEMS.with_borrow(|p| keys.iter().filter_map(|key| p.get(key)).collect())
+}
+
+/// Rebuilds the index from the live items, e.g. after an upgrade that changed how values are indexed.
+pub(crate) fn rebuild_spec_index(caller: Principal) -> Result<u64, (AuthErrorCode, String)> {
+    auth::require_owner::<AuthErrorCode>(caller)?;
+
+    SPEC_VALUE_INDEX.with_borrow_mut(|p| p.clear_new());
+    let items = live_items();
+    items.iter().for_each(index_item);
+
+    Ok(items.len() as u64)
+}
+
+/// Visits the indexed values of a label.
+fn for_each_value(
+    category: &str,
+    label: &str,
+    mut f: impl FnMut(&str, Variant, &SpecValueIndexEntry),
+) {
+    let start = SpecValueIndexKey {
+        category: category.to_string(),
+        label: label.to_string(),
+        value: String::new(),
+        variant: None,
+    };
+
+    SPEC_VALUE_INDEX.with_borrow(|p| {
+        for (key, entry) in p.range(start..) {
+            if key.category != category || key.label != label {
+                break;
+            }
+            if let Some(variant) = key.variant {
+                f(&key.value, variant, &entry);
+            }
+        }
+    });
+}
+
+impl NumberComparison {
+    fn matches(&self, left: f64, right: f64) -> bool {
+        match self {
+            NumberComparison::Less => left < right,
+            NumberComparison::LessOrEqual => left <= right,
+            NumberComparison::Equal => left == right,
+            NumberComparison::GreaterOrEqual => left >= right,
+            NumberComparison::Greater => left > right,
+        }
+    }
+}
+
+impl SpecConstraint {
+    fn matches(&self, value: &str, entry: &SpecValueIndexEntry) -> bool {
+        match self {
+            SpecConstraint::Equals(expected) => expected == value,
+            SpecConstraint::OneOf(expected) => expected.iter().any(|e| e == value),
+            SpecConstraint::Number {
+                comparison,
+                value: expected,
+                unit,
+            } => {
+                let Some(base_value) = entry.base_value else {
+                    return false;
+                };
+                match unit {
+                    Some(unit) if entry.dimension != Some(unit.dimension()) => false,
+                    Some(unit) => comparison.matches(base_value, unit.base_value(*expected)),
+                    None => comparison.matches(base_value, *expected),
+                }
+            }
+        }
+    }
+}
+
+fn matching_variants(filter: &SpecFilter) -> BTreeSet<Variant> {
+    let mut result = BTreeSet::new();
+    for_each_value(&filter.category, &filter.label, |value, variant, entry| {
+        if filter.constraint.matches(value, entry) {
+            result.insert(variant);
+        }
+    });
+    result
+}
+
+/// Counts the values of a label among `variants`, or among every indexed variant when `None`.
+fn count_facet(facet: &SpecFacetRequest, variants: Option<&BTreeSet<Variant>>) -> SpecFacet {
+    let mut items: BTreeMap<String, BTreeSet<ItemId>> = BTreeMap::new();
+    for_each_value(&facet.category, &facet.label, |value, variant, _| {
+        let counted = match variants {
+            Some(variants) => variants.contains(&variant),
+            None => true,
+        };
+        if counted {
+            items
+                .entry(value.to_string())
+                .or_default()
+                .insert(variant.0);
+        }
+    });
+
+    SpecFacet {
+        category: facet.category.clone(),
+        label: facet.label.clone(),
+        values: items
+            .into_iter()
+            .map(|(value, items)| (value, items.len() as u64))
+            .collect(),
+    }
+}
+
+/// Finds the variants that satisfy every filter, grouped by item in id order.
+///
+/// Facets count the distinct matching items for each value of the requested labels,
+/// so a client can show how many results each refinement would leave.
+/// Without filters, every live item matches and only the requested page of items is read.
+pub(crate) fn filter_items(arg: &SpecFilterRequest) -> SpecFilterResponse {
+    let limit = arg.limit.min(MAX_FILTER_LIMIT) as usize;
+
+    let Some((first, rest)) = arg.filters.split_first() else {
+        let keys: Vec<_> = ITEMS_IN_ID.with_borrow(|p| {
+            p.iter()
+                .skip(arg.offset as usize)
+                .take(limit)
+                .map(|(_, key)| key)
+                .collect()
+        });
+
+        return SpecFilterResponse {
+            total_items: ITEMS_IN_ID.with_borrow(|p| p.len()),
+            items: ITEMS.with_borrow(|p| {
+                keys.iter()
+                    .filter_map(|key| p.get(key))
+                    .map(|item| SpecFilterMatch {
+                        item_id: item.id,
+                        variants: item
+                            .variants()
+                            .into_iter()
+                            .map(|(attr_keys, _)| attr_keys)
+                            .collect(),
+                    })
+                    .collect()
+            }),
+            facets: arg
+                .facets
+                .iter()
+                .map(|facet| count_facet(facet, None))
+                .collect(),
+        };
+    };
+
+    let mut variants = matching_variants(first);
+    for filter in rest {
+        if variants.is_empty() {
+            break;
+        }
+        let matching = matching_variants(filter);
+        variants.retain(|variant| matching.contains(variant));
+    }
+
+    let mut grouped: BTreeMap<ItemId, Vec<AttrKeysV2>> = BTreeMap::new();
+    for (item_id, attr_keys) in variants.iter() {
+        grouped.entry(*item_id).or_default().push(attr_keys.clone());
+    }
+
+    SpecFilterResponse {
+        total_items: grouped.len() as u64,
+        items: grouped
+            .into_iter()
+            .skip(arg.offset as usize)
+            .take(limit)
+            .map(|(item_id, variants)| SpecFilterMatch { item_id, variants })
+            .collect(),
+        facets: arg
+            .facets
+            .iter()
+            .map(|facet| count_facet(facet, Some(&variants)))
+            .collect(),
+    }
+}
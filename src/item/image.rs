@@ -1,17 +1,71 @@
+use crate::media::{self, MediaId, MediaRendition, RenditionProfile, RenditionSettings};
 use candid::{CandidType, Deserialize};
 use common::item::MediaDataWithCaption;
-use std::collections::BTreeMap;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
 
-type ImageKey = u8;
+pub type ImageKey = u8;
 
 pub type ImageVecKey = u32;
 
+/// Gallery shown in place of a group that has no images.
+pub const DEFAULT_IMAGE_VEC_KEY: ImageVecKey = 0;
+
+/// What a gallery entry shows.
+///
+/// Every entry also has a `MediaDataWithCaption`: the image itself, the poster frame of a
+/// video or a preview render of a 3D model. Clients that only know images show that.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Default)]
+pub enum MediaKind {
+    #[default]
+    Image,
+    Video {
+        url: String,
+        mime_type: String,
+        duration_ms: u64,
+    },
+    Model3d {
+        gltf_url: Option<String>,
+        usdz_url: Option<String>,
+        // Whether the model can be placed in augmented reality
+        ar: bool,
+    },
+}
+
+/// A change to a single group of `ItemImagesV1::index_vec_map`.
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub enum GalleryOperation {
+    // Adds a new image to the item and inserts it at `position`, or at the end
+    Add {
+        image: MediaDataWithCaption,
+        position: Option<u32>,
+        kind: Option<MediaKind>,
+    },
+    // Removes the image from the group, and from the item when no other group uses it
+    Remove {
+        image_key: ImageKey,
+    },
+    // Replaces the order of the group with a permutation of its current keys
+    Reorder {
+        order: Vec<ImageKey>,
+    },
+    // Moves the image to the front of the group
+    SetBase {
+        image_key: ImageKey,
+    },
+}
+
 #[derive(CandidType, Deserialize, Debug, Clone)]
 pub struct ItemImagesV1 {
     // Actual data of images
     pub map: BTreeMap<ImageKey, MediaDataWithCaption>,
     // Mapping of image groups to keys
     pub index_vec_map: BTreeMap<ImageVecKey, Vec<ImageKey>>,
+    // Mapping of image groups to entries of the store media library.
+    // Takes precedence over `index_vec_map` for the same group.
+    pub library_vec_map: Option<BTreeMap<ImageVecKey, Vec<MediaId>>>,
+    // Kind of the images that are not plain images
+    pub kind_map: Option<BTreeMap<ImageKey, MediaKind>>,
 }
 
 impl ItemImagesV1 {
@@ -19,25 +73,225 @@ impl ItemImagesV1 {
         ItemImagesV1Builder::default()
     }
 
-    pub fn get_base_image(&self, key: &ImageVecKey) -> Option<&MediaDataWithCaption> {
-        let image_key = self.index_vec_map.get(key)?[0];
-        self.map.get(&image_key)
+    pub fn get_base_image(&self, key: &ImageVecKey) -> Option<MediaDataWithCaption> {
+        self.get_index_vec(key)?.into_iter().next()
     }
 
+    /// Resolves the images of a group, or of the default gallery when the group is empty.
     pub fn get_index_vec(&self, key: &ImageVecKey) -> Option<Vec<MediaDataWithCaption>> {
-        let image_vec = self.index_vec_map.get(key)?;
+        let key = self.gallery_key(key);
+
+        if let Some(media_vec) = self.get_library_vec(&key) {
+            // Entries removed from the library are skipped
+            let images = media_vec
+                .iter()
+                .filter_map(|id| media::get_media(*id))
+                .map(|asset| asset.media)
+                .collect();
+            return Some(images);
+        }
+
+        let image_vec = self.index_vec_map.get(&key)?;
         let mut images = Vec::new();
         for image_key in image_vec {
             images.push(self.map.get(image_key)?.clone());
         }
         Some(images)
     }
+
+    /// Returns the library entry shown first in a group, when the group is in the library.
+    pub fn get_base_media_id(&self, key: &ImageVecKey) -> Option<MediaId> {
+        self.get_library_vec(&self.gallery_key(key))?
+            .iter()
+            .copied()
+            .find(|id| media::contains_media(*id))
+    }
+
+    /// Returns the key of the group to show for `key`,
+    /// which is the default gallery when the group exists but has no images.
+    fn gallery_key(&self, key: &ImageVecKey) -> ImageVecKey {
+        let has_default = self.index_vec_map.contains_key(&DEFAULT_IMAGE_VEC_KEY)
+            || self.get_library_vec(&DEFAULT_IMAGE_VEC_KEY).is_some();
+
+        if *key != DEFAULT_IMAGE_VEC_KEY && has_default && self.is_empty_group(key) {
+            DEFAULT_IMAGE_VEC_KEY
+        } else {
+            *key
+        }
+    }
+
+    fn is_empty_group(&self, key: &ImageVecKey) -> bool {
+        match self.get_library_vec(key) {
+            Some(media_vec) => !media_vec.iter().any(|id| media::contains_media(*id)),
+            None => self
+                .index_vec_map
+                .get(key)
+                .is_some_and(|image_vec| image_vec.is_empty()),
+        }
+    }
+
+    /// Returns the kind of each entry of a group, in the order of `get_index_vec`.
+    pub fn get_index_kinds(&self, key: &ImageVecKey) -> Vec<MediaKind> {
+        let key = self.gallery_key(key);
+
+        if let Some(media_vec) = self.get_library_vec(&key) {
+            return media_vec
+                .iter()
+                .filter_map(|id| media::get_media(*id))
+                .map(|asset| asset.kind.unwrap_or_default())
+                .collect();
+        }
+
+        self.index_vec_map
+            .get(&key)
+            .map_or(Vec::new(), |image_vec| {
+                image_vec
+                    .iter()
+                    .map(|image_key| {
+                        self.kind_map
+                            .as_ref()
+                            .and_then(|kind_map| kind_map.get(image_key))
+                            .cloned()
+                            .unwrap_or_default()
+                    })
+                    .collect()
+            })
+    }
+
+    /// Builds the renditions of each image of a group, in the order of `get_index_vec`.
+    /// Images that are not in the store media library have no renditions.
+    pub fn get_index_renditions(
+        &self,
+        key: &ImageVecKey,
+        settings: &RenditionSettings,
+        profile: &RenditionProfile,
+    ) -> Vec<Vec<MediaRendition>> {
+        let key = self.gallery_key(key);
+
+        if let Some(media_vec) = self.get_library_vec(&key) {
+            return media_vec
+                .iter()
+                .filter_map(|id| {
+                    let asset = media::get_media(*id)?;
+                    Some(settings.renditions(*id, &asset.metadata, profile))
+                })
+                .collect();
+        }
+
+        self.index_vec_map
+            .get(&key)
+            .map_or(Vec::new(), |image_vec| vec![Vec::new(); image_vec.len()])
+    }
+
+    /// Whether any image group references the library entry.
+    pub fn references_media(&self, id: MediaId) -> bool {
+        self.library_vec_map
+            .as_ref()
+            .is_some_and(|library_vec_map| library_vec_map.values().any(|ids| ids.contains(&id)))
+    }
+
+    /// Lists the keys of every image group, from both the item's own images and the library.
+    pub fn group_keys(&self) -> BTreeSet<ImageVecKey> {
+        let mut keys: BTreeSet<ImageVecKey> = self.index_vec_map.keys().copied().collect();
+        if let Some(library_vec_map) = &self.library_vec_map {
+            keys.extend(library_vec_map.keys());
+        }
+        keys
+    }
+
+    /// Applies a gallery operation to a group and returns the new order of the group.
+    ///
+    /// Only `index_vec_map` is changed, so a group that is also in `library_vec_map`
+    /// keeps showing the library images.
+    pub fn apply_gallery_operation(
+        &mut self,
+        key: ImageVecKey,
+        op: GalleryOperation,
+    ) -> Result<Vec<ImageKey>, String> {
+        match op {
+            GalleryOperation::Add {
+                image,
+                position,
+                kind,
+            } => {
+                let image_key = (ImageKey::MIN..=ImageKey::MAX)
+                    .find(|k| !self.map.contains_key(k))
+                    .ok_or_else(|| "The item has no free image key left".to_string())?;
+
+                self.map.insert(image_key, image);
+                if let Some(kind) = kind {
+                    self.kind_map
+                        .get_or_insert_with(BTreeMap::new)
+                        .insert(image_key, kind);
+                }
+
+                let group = self.index_vec_map.entry(key).or_default();
+                let position = position.map_or(group.len(), |p| group.len().min(p as usize));
+                group.insert(position, image_key);
+            }
+            GalleryOperation::Remove { image_key } => {
+                let group = self.get_group_mut(key)?;
+                let position = find_image(group, key, image_key)?;
+                group.remove(position);
+
+                if !self.index_vec_map.values().any(|g| g.contains(&image_key)) {
+                    self.map.remove(&image_key);
+                    if let Some(kind_map) = self.kind_map.as_mut() {
+                        kind_map.remove(&image_key);
+                    }
+                }
+            }
+            GalleryOperation::Reorder { order } => {
+                let group = self.get_group_mut(key)?;
+
+                let mut current = group.clone();
+                current.sort();
+                let mut requested = order.clone();
+                requested.sort();
+                if current != requested {
+                    return Err(format!(
+                        "Order {:?} is not a permutation of image group {}",
+                        order, key
+                    ));
+                }
+
+                *group = order;
+            }
+            GalleryOperation::SetBase { image_key } => {
+                let group = self.get_group_mut(key)?;
+                let position = find_image(group, key, image_key)?;
+                group.remove(position);
+                group.insert(0, image_key);
+            }
+        }
+
+        Ok(self.index_vec_map.get(&key).cloned().unwrap_or_default())
+    }
+
+    fn get_group_mut(&mut self, key: ImageVecKey) -> Result<&mut Vec<ImageKey>, String> {
+        self.index_vec_map
+            .get_mut(&key)
+            .ok_or_else(|| format!("Image group {} not found", key))
+    }
+
+    fn get_library_vec(&self, key: &ImageVecKey) -> Option<&Vec<MediaId>> {
+        self.library_vec_map.as_ref()?.get(key)
+    }
+}
+
+fn find_image(group: &[ImageKey], key: ImageVecKey, image_key: ImageKey) -> Result<usize, String> {
+    group
+        .iter()
+        .position(|k| *k == image_key)
+        .ok_or_else(|| format!("Image {} not found in image group {}", image_key, key))
 }
 
 #[derive(Default)]
 pub struct ItemImagesV1Builder {
     pub map: BTreeMap<ImageKey, MediaDataWithCaption>,
     pub index_vec_map: BTreeMap<ImageVecKey, Vec<ImageKey>>,
+    pub library_vec_map: BTreeMap<ImageVecKey, Vec<MediaId>>,
+    pub kind_map: BTreeMap<ImageKey, MediaKind>,
 }
 
 impl ItemImagesV1Builder {
@@ -51,10 +305,25 @@ impl ItemImagesV1Builder {
         self
     }
 
+    /// Marks an image as the poster or preview of a video or 3D model.
+    pub fn media_kind(&mut self, key: ImageKey, kind: MediaKind) -> &mut Self {
+        self.kind_map.insert(key, kind);
+        self
+    }
+
+    /// Adds an image group that references entries of the store media library.
+    pub fn library_vec(&mut self, key: ImageVecKey, media_vec: Vec<MediaId>) -> &mut Self {
+        self.library_vec_map.insert(key, media_vec);
+        self
+    }
+
     pub fn build(&self) -> ItemImagesV1 {
         ItemImagesV1 {
             map: self.map.clone(),
             index_vec_map: self.index_vec_map.clone(),
+            library_vec_map: (!self.library_vec_map.is_empty())
+                .then(|| self.library_vec_map.clone()),
+            kind_map: (!self.kind_map.is_empty()).then(|| self.kind_map.clone()),
         }
     }
 }
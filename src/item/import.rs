@@ -0,0 +1,798 @@
+use super::{
+    attr::{
+        AttrIndex, AttrIndexes, AttrKeysV2, AttrSpecificData, ItemAttrsV1, ItemAttrsV2,
+        V1_DIMENSIONS,
+    },
+    image::{ImageKey, ImageVecKey, ItemImagesV1},
+    spec::{ItemSpecsV1, SpecCategory, SpecIndexKey, SpecKey, SpecLabel},
+    Item, ItemId, ItemKey, ItemName, ItemVersion, Tag,
+};
+use crate::{
+    auth::{self, AuthErrorCode},
+    media::{self, MediaId},
+};
+use candid::{CandidType, Deserialize, Encode, Principal};
+use common::{
+    item::{
+        attr::{AttrKey, AttrType, Stock},
+        MediaDataWithCaption,
+    },
+    unit::Currency,
+};
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+
+/// Largest document accepted by `import_items`, leaving room in the 2 MiB ingress limit.
+pub const MAX_IMPORT_SIZE: usize = 1_900_000;
+
+/// Separates the values of a multi-valued CSV cell, such as `tags` or `image_urls`.
+const CSV_LIST_SEPARATOR: char = ';';
+
+#[derive(CandidType, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    // A header row followed by one row per variant
+    Csv,
+    // An array of `ImportRow` objects
+    Json,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct ImportRequest {
+    pub format: ImportFormat,
+    pub data: String,
+    // Validates and assembles the items without storing them
+    pub dry_run: Option<bool>,
+}
+
+/// A single variant of an item. Rows with the same `item_id` make up one item,
+/// and the item-level fields are taken from the first of them.
+///
+/// In CSV, the columns are `item_id`, `name`, `description`, `tags`, `stock`, `image_urls`
+/// and `media_ids`, plus one `attr:{dimension}` column per dimension, one `price:{currency}`
+/// column per currency and one `spec:{category}:{label}` column per spec. Empty cells are
+/// skipped. Items with more than four dimensions are imported as V2 items.
+#[derive(Deserialize, Debug, Clone)]
+pub struct ImportRow {
+    pub item_id: ItemId,
+    pub name: ItemName,
+    #[serde(default)]
+    pub descriptions: Vec<String>,
+    #[serde(default)]
+    pub tags: Vec<Tag>,
+    // Dimension name and value, in dimension order
+    #[serde(default)]
+    pub attrs: Vec<ImportAttr>,
+    pub prices: Vec<ImportPrice>,
+    pub stock: Stock,
+    // Images of the variant. Rows with the same images share an image group.
+    #[serde(default)]
+    pub images: Vec<MediaDataWithCaption>,
+    // Entries of the store media library, shown instead of `images` when set
+    #[serde(default)]
+    pub media_ids: Vec<MediaId>,
+    #[serde(default)]
+    pub specs: Vec<ImportSpec>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ImportAttr {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ImportPrice {
+    pub currency: Currency,
+    pub amount: f64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ImportSpec {
+    pub category: String,
+    pub label: String,
+    pub value: String,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum ImportErrorCode {
+    Unauthorized,
+    TooLarge,
+    InvalidFormat,
+}
+
+impl From<AuthErrorCode> for ImportErrorCode {
+    fn from(code: AuthErrorCode) -> Self {
+        match code {
+            AuthErrorCode::Unauthorized => ImportErrorCode::Unauthorized,
+        }
+    }
+}
+
+/// An error of a single row. Rows are numbered from 1, not counting the CSV header.
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct ImportRowError {
+    pub row: u32,
+    pub item_id: Option<ItemId>,
+    pub message: String,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, Default)]
+pub struct ImportReport {
+    // Empty on a dry run
+    pub imported: Vec<(ItemId, ItemKey)>,
+    // Items with at least one invalid row, which are not imported. Every item when a row
+    // without a readable item id is invalid.
+    pub skipped: Vec<ItemId>,
+    pub item_count: u32,
+    pub errors: Vec<ImportRowError>,
+}
+
+/// Assembles items from flat variant rows and inserts them.
+///
+/// An item is only imported when all of its rows are valid, so a store never ends up
+/// with part of an item. Items that already exist are replaced and kept as a revision.
+pub(crate) async fn import_items(
+    author: Principal,
+    arg: ImportRequest,
+) -> Result<ImportReport, (ImportErrorCode, String)> {
+    let author = auth::require_staff::<ImportErrorCode>(author)?;
+
+    if arg.data.len() > MAX_IMPORT_SIZE {
+        return Err((
+            ImportErrorCode::TooLarge,
+            format!("Import data must be at most {} bytes", MAX_IMPORT_SIZE),
+        ));
+    }
+
+    let mut report = ImportReport::default();
+
+    let rows = match arg.format {
+        ImportFormat::Csv => parse_csv(&arg.data)?,
+        ImportFormat::Json => parse_json(&arg.data)?,
+    };
+
+    let mut items = Vec::new();
+    for (item_id, group) in group_rows(rows, &mut report) {
+        match assemble_item(item_id, group) {
+            Ok(item) => items.push(item),
+            Err(errors) => {
+                report.skipped.push(item_id);
+                report.errors.extend(errors);
+            }
+        }
+    }
+
+    report.item_count = items.len() as u32;
+    if !arg.dry_run.unwrap_or_default() && !items.is_empty() {
+        report.imported = super::insert_items(author, items).await;
+    }
+
+    Ok(report)
+}
+
+type ParsedRows = Vec<(u32, Result<ImportRow, (Option<ItemId>, String)>)>;
+
+/// Groups rows by item, in the order the items first appear, and reports row errors.
+///
+/// Items with a failed row are skipped rather than imported without it. A failed row whose
+/// item id can't be read may belong to any item, so then every item is skipped.
+fn group_rows(rows: ParsedRows, report: &mut ImportReport) -> Vec<(ItemId, Vec<(u32, ImportRow)>)> {
+    let mut groups: Vec<(ItemId, Vec<(u32, ImportRow)>)> = Vec::new();
+    for (row_number, row) in rows {
+        match row {
+            Ok(row) => match groups.iter_mut().find(|(id, _)| *id == row.item_id) {
+                Some((_, group)) => group.push((row_number, row)),
+                None => groups.push((row.item_id, vec![(row_number, row)])),
+            },
+            Err((item_id, message)) => report.errors.push(ImportRowError {
+                row: row_number,
+                item_id,
+                message,
+            }),
+        }
+    }
+
+    let unknown_item_failed = report.errors.iter().any(|error| error.item_id.is_none());
+    let failed_items: Vec<ItemId> = report
+        .errors
+        .iter()
+        .filter_map(|error| error.item_id)
+        .collect();
+
+    groups
+        .into_iter()
+        .filter(|(item_id, _)| {
+            let failed = unknown_item_failed || failed_items.contains(item_id);
+            if failed {
+                report.skipped.push(*item_id);
+            }
+            !failed
+        })
+        .collect()
+}
+
+fn parse_json(data: &str) -> Result<ParsedRows, (ImportErrorCode, String)> {
+    let values: Vec<serde_json::Value> = serde_json::from_str(data).map_err(|e| {
+        (
+            ImportErrorCode::InvalidFormat,
+            format!("Import data must be a JSON array of rows: {}", e),
+        )
+    })?;
+
+    Ok(values
+        .into_iter()
+        .enumerate()
+        .map(|(i, value)| {
+            let item_id = value
+                .get("item_id")
+                .and_then(|id| serde_json::from_value(id.clone()).ok());
+            let row = serde_json::from_value(value).map_err(|e| (item_id, e.to_string()));
+            (i as u32 + 1, row)
+        })
+        .collect())
+}
+
+fn parse_csv(data: &str) -> Result<ParsedRows, (ImportErrorCode, String)> {
+    let mut records = parse_csv_records(data)
+        .map_err(|message| (ImportErrorCode::InvalidFormat, message))?
+        .into_iter();
+
+    let header = records.next().ok_or((
+        ImportErrorCode::InvalidFormat,
+        "Import data has no header row".to_string(),
+    ))?;
+    for column in ["item_id", "name", "stock"] {
+        if !header.iter().any(|h| h == column) {
+            return Err((
+                ImportErrorCode::InvalidFormat,
+                format!("Header has no {} column", column),
+            ));
+        }
+    }
+
+    Ok(records
+        .enumerate()
+        .filter(|(_, record)| record.iter().any(|cell| !cell.is_empty()))
+        .map(|(i, record)| (i as u32 + 1, csv_row(&header, &record)))
+        .collect())
+}
+
+/// Reads a CSV cell as `T`, as a string first and then as a JSON value,
+/// so that both string and numeric ids and amounts are accepted.
+fn from_cell<T: DeserializeOwned>(column: &str, cell: &str) -> Result<T, String> {
+    serde_json::from_value(serde_json::Value::String(cell.to_string()))
+        .or_else(|_| serde_json::from_str(cell))
+        .map_err(|_| format!("Invalid value {:?} in column {}", cell, column))
+}
+
+fn from_list_cell<T: DeserializeOwned>(column: &str, cell: &str) -> Result<Vec<T>, String> {
+    cell.split(CSV_LIST_SEPARATOR)
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(|value| from_cell(column, value))
+        .collect()
+}
+
+fn csv_row(header: &[String], record: &[String]) -> Result<ImportRow, (Option<ItemId>, String)> {
+    let cell = |column: &str| {
+        header
+            .iter()
+            .position(|h| h == column)
+            .and_then(|i| record.get(i))
+            .map_or("", |value| value.trim())
+    };
+
+    // Read first so that even a malformed row skips its item
+    let item_id: ItemId = from_cell("item_id", cell("item_id")).map_err(|e| (None, e))?;
+    let with_id = |message: String| (Some(item_id), message);
+
+    if record.len() != header.len() {
+        return Err(with_id(format!(
+            "Row has {} cells but the header has {} columns",
+            record.len(),
+            header.len()
+        )));
+    }
+
+    let mut row = ImportRow {
+        item_id,
+        name: from_cell("name", cell("name")).map_err(with_id)?,
+        descriptions: Vec::new(),
+        tags: from_list_cell("tags", cell("tags")).map_err(with_id)?,
+        attrs: Vec::new(),
+        prices: Vec::new(),
+        stock: from_cell("stock", cell("stock")).map_err(with_id)?,
+        images: cell("image_urls")
+            .split(CSV_LIST_SEPARATOR)
+            .map(str::trim)
+            .filter(|url| !url.is_empty())
+            .map(media::media_from_url)
+            .collect::<Result<_, _>>()
+            .map_err(with_id)?,
+        media_ids: from_list_cell("media_ids", cell("media_ids")).map_err(with_id)?,
+        specs: Vec::new(),
+    };
+
+    if !cell("description").is_empty() {
+        row.descriptions.push(cell("description").to_string());
+    }
+
+    for (column, value) in header.iter().zip(record) {
+        let value = value.trim();
+        if value.is_empty() {
+            continue;
+        }
+
+        if let Some(name) = column.strip_prefix("attr:") {
+            row.attrs.push(ImportAttr {
+                name: name.to_string(),
+                value: value.to_string(),
+            });
+        } else if let Some(currency) = column.strip_prefix("price:") {
+            row.prices.push(ImportPrice {
+                currency: from_cell(column, currency).map_err(with_id)?,
+                amount: from_cell(column, value).map_err(with_id)?,
+            });
+        } else if let Some(spec) = column.strip_prefix("spec:") {
+            let (category, label) = spec.split_once(':').ok_or_else(|| {
+                with_id(format!(
+                    "Column {} must be spec:{{category}}:{{label}}",
+                    column
+                ))
+            })?;
+            row.specs.push(ImportSpec {
+                category: category.to_string(),
+                label: label.to_string(),
+                value: value.to_string(),
+            });
+        }
+    }
+
+    Ok(row)
+}
+
+/// Splits CSV text into records of cells, following RFC 4180 quoting.
+fn parse_csv_records(data: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut cell = String::new();
+    let mut in_quotes = false;
+
+    let mut chars = data.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, in_quotes) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                cell.push('"');
+            }
+            ('"', true) => in_quotes = false,
+            ('"', false) if cell.is_empty() => in_quotes = true,
+            (',', false) => record.push(std::mem::take(&mut cell)),
+            ('\r', false) if chars.peek() == Some(&'\n') => {}
+            ('\n' | '\r', false) => {
+                record.push(std::mem::take(&mut cell));
+                records.push(std::mem::take(&mut record));
+            }
+            (c, _) => cell.push(c),
+        }
+    }
+
+    if in_quotes {
+        return Err("Import data ends inside a quoted cell".to_string());
+    }
+    if !cell.is_empty() || !record.is_empty() {
+        record.push(cell);
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+/// Finds the position of `value` in `keys`, adding it when missing.
+/// Fails once the `u8` keys are used up.
+fn assign_key<T: PartialEq>(keys: &mut Vec<T>, value: T, what: &str) -> Result<u8, String> {
+    let position = match keys.iter().position(|k| *k == value) {
+        Some(position) => position,
+        None => {
+            keys.push(value);
+            keys.len() - 1
+        }
+    };
+    u8::try_from(position).map_err(|_| format!("Item has more than 256 {}", what))
+}
+
+/// Keys assigned so far while assembling an item, in the order they first appear.
+#[derive(Default)]
+struct ItemAssembly {
+    // Dimension names, then the values of each dimension
+    dimensions: Vec<String>,
+    dimension_values: Vec<Vec<String>>,
+    // Candid encoding of each distinct image, then each distinct image group
+    images: Vec<Vec<u8>>,
+    image_map: BTreeMap<ImageKey, MediaDataWithCaption>,
+    image_groups: Vec<(Vec<ImageKey>, Vec<MediaId>)>,
+    // Spec categories, the labels of each category and the values of each label
+    categories: Vec<String>,
+    labels: Vec<Vec<String>>,
+    values: Vec<Vec<Vec<String>>>,
+    // Category key followed by label and value key pairs
+    spec_keys: Vec<Vec<u8>>,
+    variants: BTreeMap<AttrKeysV2, AttrSpecificData>,
+}
+
+impl ItemAssembly {
+    fn add_row(&mut self, row: &ImportRow) -> Result<(), String> {
+        if row.prices.is_empty() {
+            return Err("Row has no price".to_string());
+        }
+
+        let mut attr_keys = AttrKeysV2::default();
+        for attr in &row.attrs {
+            let dimension = assign_key(&mut self.dimensions, attr.name.clone(), "dimensions")?;
+            if self.dimension_values.len() <= dimension as usize {
+                self.dimension_values.push(Vec::new());
+            }
+            let key = assign_key(
+                &mut self.dimension_values[dimension as usize],
+                attr.value.clone(),
+                "values in a dimension",
+            )?;
+            attr_keys = attr_keys.replace(dimension as usize, key as AttrKey);
+        }
+        // Earlier rows may have fewer dimensions, whose keys are default in the missing ones
+        let dimensions = self.dimensions.len();
+        let attr_keys = attr_keys.normalized(dimensions);
+
+        if self
+            .variants
+            .keys()
+            .any(|keys| keys.normalized(dimensions) == attr_keys)
+        {
+            return Err("Another row of the item has the same attribute values".to_string());
+        }
+
+        let mut image_keys = Vec::new();
+        for image in &row.images {
+            let encoded = Encode!(image).map_err(|e| e.to_string())?;
+            let image_key = assign_key(&mut self.images, encoded, "images")?;
+            self.image_map.insert(image_key, image.clone());
+            image_keys.push(image_key);
+        }
+        let image_vec_key = assign_key(
+            &mut self.image_groups,
+            (image_keys, row.media_ids.clone()),
+            "image groups",
+        )?;
+
+        let mut spec_by_category: BTreeMap<u8, Vec<u8>> = BTreeMap::new();
+        for spec in &row.specs {
+            let category = assign_key(
+                &mut self.categories,
+                spec.category.clone(),
+                "spec categories",
+            )? as usize;
+            if self.labels.len() <= category {
+                self.labels.push(Vec::new());
+                self.values.push(Vec::new());
+            }
+            let label = assign_key(
+                &mut self.labels[category],
+                spec.label.clone(),
+                "spec labels in a category",
+            )?;
+            if self.values[category].len() <= label as usize {
+                self.values[category].push(Vec::new());
+            }
+            let value = assign_key(
+                &mut self.values[category][label as usize],
+                spec.value.clone(),
+                "values of a spec label",
+            )?;
+            spec_by_category
+                .entry(category as u8)
+                .or_insert_with(|| vec![category as u8])
+                .extend([label, value]);
+        }
+
+        let mut data = AttrSpecificData::builder()
+            .stock(row.stock)
+            .image_vec_key(image_vec_key as ImageVecKey);
+        for price in &row.prices {
+            data = data.price(price.currency.clone(), price.amount);
+        }
+        for spec_key in spec_by_category.into_values() {
+            let index = assign_key(&mut self.spec_keys, spec_key, "spec combinations")?;
+            data = data.spec_key(index as SpecIndexKey);
+        }
+
+        self.variants.insert(attr_keys, data.build());
+        Ok(())
+    }
+
+    fn build(self, id: ItemId, first: ImportRow) -> Result<Item, String> {
+        let dimensions = self.dimensions.len();
+        let attr_indexes: Vec<AttrIndex> = self
+            .dimensions
+            .iter()
+            .zip(&self.dimension_values)
+            .map(|(name, values)| {
+                let mut index = AttrIndex::builder(name);
+                for (key, value) in values.iter().enumerate() {
+                    index = index.label(key as AttrKey, AttrType::Text(value.clone()));
+                }
+                index.build()
+            })
+            .collect();
+
+        let mut images = ItemImagesV1::builder();
+        for (key, image) in self.image_map {
+            images.image(key, image);
+        }
+        for (key, (image_keys, media_ids)) in self.image_groups.into_iter().enumerate() {
+            images.index_vec(key as ImageVecKey, image_keys);
+            if !media_ids.is_empty() {
+                images.library_vec(key as ImageVecKey, media_ids);
+            }
+        }
+
+        let mut specs = ItemSpecsV1::builder();
+        for (category_key, category_name) in self.categories.iter().enumerate() {
+            let mut category = SpecCategory::builder(category_name);
+            for (label_key, label_name) in self.labels[category_key].iter().enumerate() {
+                let mut label = SpecLabel::builder(label_name);
+                for (value_key, value) in self.values[category_key][label_key].iter().enumerate() {
+                    label = label.value(value_key as u8, vec![value.as_str()]);
+                }
+                category = category.label(label_key as u8, label.build());
+            }
+            specs = specs.spec(category_key as u8, category.build());
+        }
+        for (index, spec_key) in self.spec_keys.iter().enumerate() {
+            let mut key = SpecKey::builder(spec_key[0]);
+            for pair in spec_key[1..].chunks(2) {
+                key = key.label(pair[0], pair[1]);
+            }
+            specs = specs.index(index as SpecIndexKey, key.build());
+        }
+
+        let version = if dimensions <= V1_DIMENSIONS {
+            let mut indexes = AttrIndexes::builder();
+            for (i, index) in attr_indexes.into_iter().enumerate() {
+                indexes = indexes.attr(i, index);
+            }
+
+            let mut attrs = ItemAttrsV1::builder().indexes(indexes.build());
+            for (attr_keys, data) in self.variants {
+                if let Some(attr_keys) = attr_keys.normalized(V1_DIMENSIONS).to_v1() {
+                    attrs = attrs.attr(attr_keys, data);
+                }
+            }
+
+            ItemVersion::V1 {
+                descriptions: first.descriptions,
+                tags: first.tags,
+                images: images.build(),
+                specs: specs.build(),
+                attrs: attrs.build(),
+            }
+        } else {
+            let mut attrs = ItemAttrsV2::builder();
+            for index in attr_indexes {
+                attrs = attrs.index(index);
+            }
+            for (attr_keys, data) in self.variants {
+                attrs = attrs.attr(attr_keys.0, data);
+            }
+
+            ItemVersion::V2 {
+                descriptions: first.descriptions,
+                tags: first.tags,
+                images: images.build(),
+                specs: specs.build(),
+                attrs: attrs.build()?,
+            }
+        };
+
+        Ok(Item {
+            id,
+            name: first.name,
+            version,
+            fallback_strategy: None,
+            translations: None,
+            bundle: None,
+        })
+    }
+}
+
+/// Builds an item from its rows, numbering attribute, image and spec keys
+/// in the order they first appear.
+fn assemble_item(
+    item_id: ItemId,
+    rows: Vec<(u32, ImportRow)>,
+) -> Result<Item, Vec<ImportRowError>> {
+    let mut assembly = ItemAssembly::default();
+
+    let errors: Vec<ImportRowError> = rows
+        .iter()
+        .filter_map(|(row_number, row)| {
+            let message = assembly.add_row(row).err()?;
+            Some(ImportRowError {
+                row: *row_number,
+                item_id: Some(item_id),
+                message,
+            })
+        })
+        .collect();
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    // Groups are never empty, so there is always a first row
+    let (row, first) = rows.into_iter().next().ok_or_else(Vec::new)?;
+    assembly.build(item_id, first).map_err(|message| {
+        vec![ImportRowError {
+            row,
+            item_id: Some(item_id),
+            message,
+        }]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(data: &str) -> Vec<ImportRow> {
+        parse_csv(data)
+            .unwrap()
+            .into_iter()
+            .map(|(_, row)| row.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn quoted_cells_keep_separators_and_quotes() {
+        let records =
+            parse_csv_records("a,\"b,c\",\"say \"\"hi\"\"\"\n\"line\nbreak\",,x\r\n").unwrap();
+
+        assert_eq!(
+            records,
+            vec![vec!["a", "b,c", "say \"hi\""], vec!["line\nbreak", "", "x"],]
+        );
+    }
+
+    #[test]
+    fn unterminated_quote_is_rejected() {
+        assert!(parse_csv_records("a,\"b\n").is_err());
+    }
+
+    #[test]
+    fn header_missing_a_required_column_is_rejected() {
+        let (code, message) = parse_csv("item_id,name,price:USD\n1,Shirt,10\n").unwrap_err();
+
+        assert_eq!(code, ImportErrorCode::InvalidFormat);
+        assert!(message.contains("stock"));
+    }
+
+    #[test]
+    fn row_with_wrong_cell_count_is_a_row_error() {
+        let parsed = parse_csv("item_id,name,stock,price:USD\n1,Shirt,5\n1,Shirt,5,10\n").unwrap();
+
+        assert_eq!(parsed.len(), 2);
+        let (row_number, row) = &parsed[0];
+        assert_eq!(*row_number, 1);
+        let (item_id, message) = row.as_ref().unwrap_err();
+        assert_eq!(item_id.map(|id| id.to_string()), Some("1".to_string()));
+        assert!(message.contains("3 cells"));
+        assert!(parsed[1].1.is_ok());
+
+        let mut report = ImportReport::default();
+        assert!(group_rows(parsed, &mut report).is_empty());
+        assert_eq!(report.skipped.len(), 1);
+    }
+
+    #[test]
+    fn row_without_a_readable_item_id_skips_every_item() {
+        let parsed =
+            parse_csv("item_id,name,stock,price:USD\n1,Shirt,5,10\n[],Shirt,5,10\n").unwrap();
+
+        let mut report = ImportReport::default();
+        let groups = group_rows(parsed, &mut report);
+
+        assert!(groups.is_empty());
+        assert_eq!(report.errors.len(), 1);
+        assert!(report.errors[0].item_id.is_none());
+        assert_eq!(report.skipped.len(), 1);
+    }
+
+    #[test]
+    fn attr_keys_are_numbered_in_order_of_appearance() {
+        let rows = rows(
+            "item_id,name,stock,price:USD,attr:color,attr:size\n\
+             1,Shirt,5,10,red,S\n\
+             1,Shirt,5,10,blue,S\n\
+             1,Shirt,5,10,red,M\n",
+        );
+
+        let mut assembly = ItemAssembly::default();
+        for row in &rows {
+            assembly.add_row(row).unwrap();
+        }
+
+        assert_eq!(assembly.dimensions, vec!["color", "size"]);
+        assert_eq!(
+            assembly.dimension_values,
+            vec![vec!["red", "blue"], vec!["S", "M"]]
+        );
+        let keys: Vec<AttrKeysV2> = assembly.variants.keys().cloned().collect();
+        assert_eq!(
+            keys,
+            vec![
+                AttrKeysV2(vec![0, 0]),
+                AttrKeysV2(vec![0, 1]),
+                AttrKeysV2(vec![1, 0]),
+            ]
+        );
+    }
+
+    #[test]
+    fn rows_with_the_same_attr_values_are_rejected() {
+        let rows = rows(
+            "item_id,name,stock,price:USD,attr:color\n\
+             1,Shirt,5,10,red\n\
+             1,Shirt,3,12,red\n",
+        );
+
+        let mut assembly = ItemAssembly::default();
+        assembly.add_row(&rows[0]).unwrap();
+        assert!(assembly.add_row(&rows[1]).is_err());
+    }
+
+    #[test]
+    fn image_keys_are_shared_between_rows() {
+        let rows = rows(
+            "item_id,name,stock,price:USD,attr:color,image_urls\n\
+             1,Shirt,5,10,red,https://example.com/a.jpg\n\
+             1,Shirt,5,10,blue,https://example.com/a.jpg;https://example.com/b.jpg\n\
+             1,Shirt,5,10,green,https://example.com/a.jpg\n",
+        );
+
+        let mut assembly = ItemAssembly::default();
+        for row in &rows {
+            assembly.add_row(row).unwrap();
+        }
+
+        assert_eq!(
+            assembly.image_map.keys().copied().collect::<Vec<_>>(),
+            vec![0, 1]
+        );
+        assert_eq!(
+            assembly.image_groups,
+            vec![(vec![0], Vec::new()), (vec![0, 1], Vec::new())]
+        );
+        let image_vec_keys: Vec<ImageVecKey> = assembly
+            .variants
+            .values()
+            .map(|data| data.image_vec_key)
+            .collect();
+        assert_eq!(image_vec_keys, vec![0, 1, 0]);
+    }
+
+    #[test]
+    fn more_than_four_dimensions_build_a_v2_item() {
+        let rows = rows(
+            "item_id,name,stock,price:USD,attr:a,attr:b,attr:c,attr:d,attr:e\n\
+             1,Shirt,5,10,1,1,1,1,1\n\
+             1,Shirt,5,10,1,1,1,1,2\n",
+        );
+        let item_id = rows[0].item_id;
+
+        let item = assemble_item(item_id, rows.into_iter().map(|row| (1, row)).collect()).unwrap();
+
+        let ItemVersion::V2 { attrs, .. } = &item.version else {
+            panic!("expected a V2 item");
+        };
+        assert_eq!(attrs.indexes.len(), 5);
+        assert!(attrs.map.contains_key(&AttrKeysV2(vec![0, 0, 0, 0, 1])));
+    }
+}
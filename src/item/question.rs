@@ -0,0 +1,528 @@
+use super::{attr::AttrKeysV2, get_item, review, Item, ItemId};
+use crate::{
+    auth::{self, AuthErrorCode},
+    data, QA_VOTES, QUESTIONS,
+};
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{storable::Bound, Storable};
+use serde::Serialize;
+use std::borrow::Cow;
+
+pub type QuestionId = u64;
+// Index of an answer in its question
+pub type AnswerIndex = u32;
+
+pub const MAX_QA_TEXT_LENGTH: usize = 2000;
+/// Largest number of answers by buyers that are not hidden. Staff can always answer,
+/// so answers waiting for moderation cannot lock them out.
+pub const MAX_ANSWERS_PER_QUESTION: usize = 50;
+pub const MAX_CITATIONS_PER_ANSWER: usize = 10;
+/// Largest number of questions about an item waiting for moderation. New questions are
+/// rejected until the owner catches up, so unmoderated posts cannot grow without bound.
+pub const MAX_PENDING_QUESTIONS_PER_ITEM: usize = 200;
+/// Largest page of questions returned by one query.
+pub const MAX_QUESTION_LIMIT: u64 = 50;
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct QuestionKey {
+    pub item_id: ItemId,
+    pub question_id: QuestionId,
+}
+
+impl Storable for QuestionKey {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QaStatus {
+    // Waiting for moderation, only visible to its author and staff
+    Pending,
+    Published,
+    Hidden,
+}
+
+/// Part of an item an answer refers to.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub enum AnswerCitation {
+    Variant(AttrKeysV2),
+    Spec {
+        category_name: String,
+        label_name: String,
+    },
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct Answer {
+    pub author: Principal,
+    pub text: String,
+    pub citations: Vec<AnswerCitation>,
+    pub status: QaStatus,
+    pub votes: u64,
+    // Whether the author answered as staff, rather than as a verified buyer
+    pub by_staff: bool,
+    pub created_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct Question {
+    pub author: Principal,
+    pub text: String,
+    pub status: QaStatus,
+    pub votes: u64,
+    pub answers: Vec<Answer>,
+    pub created_at: u64,
+}
+
+impl Storable for Question {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Key of a vote, so that a principal votes at most once for a question or an answer.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct QaVoteKey {
+    pub question: QuestionKey,
+    // `None` for a vote for the question itself
+    pub answer: Option<AnswerIndex>,
+    pub voter: Principal,
+}
+
+impl Storable for QaVoteKey {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct AnswerInput {
+    pub text: String,
+    pub citations: Vec<AnswerCitation>,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, Copy)]
+pub enum QaModeration {
+    Publish,
+    Hide,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuestionSort {
+    Votes,
+    Recent,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct QuestionQuery {
+    pub offset: u64,
+    pub limit: u64,
+    pub sort: QuestionSort,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct QuestionPage {
+    // Published questions with their published answers, most voted answers first
+    pub questions: Vec<(QuestionId, Question)>,
+    pub total: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum QaErrorCode {
+    Unauthorized,
+    ItemNotFound,
+    QuestionNotFound,
+    AnswerNotFound,
+    InvalidCitation,
+    InvalidText,
+    TooManyAnswers,
+    TooManyPendingQuestions,
+    AlreadyVoted,
+}
+
+impl From<AuthErrorCode> for QaErrorCode {
+    fn from(code: AuthErrorCode) -> Self {
+        match code {
+            AuthErrorCode::Unauthorized => QaErrorCode::Unauthorized,
+        }
+    }
+}
+
+fn validate_text(text: &str) -> Result<(), (QaErrorCode, String)> {
+    if text.trim().is_empty() || text.chars().count() > MAX_QA_TEXT_LENGTH {
+        return Err((
+            QaErrorCode::InvalidText,
+            format!(
+                "Text must be between 1 and {} characters",
+                MAX_QA_TEXT_LENGTH
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that every cited variant and spec label exists in the item,
+/// normalizing the keys of cited variants to the dimensions of the item.
+fn validate_citations(
+    item: &Item,
+    citations: &mut [AnswerCitation],
+) -> Result<(), (QaErrorCode, String)> {
+    if citations.len() > MAX_CITATIONS_PER_ANSWER {
+        return Err((
+            QaErrorCode::InvalidCitation,
+            format!(
+                "An answer can cite at most {} variants and specs",
+                MAX_CITATIONS_PER_ANSWER
+            ),
+        ));
+    }
+
+    for citation in citations.iter_mut() {
+        match citation {
+            AnswerCitation::Variant(attr_keys) => {
+                *attr_keys = attr_keys.normalized(item.dimensions());
+                if item.get_variant(attr_keys).is_none() {
+                    return Err((
+                        QaErrorCode::InvalidCitation,
+                        format!(
+                            "Attribute with keys {:?} of item with id {} not found in the store",
+                            attr_keys, item.id
+                        ),
+                    ));
+                }
+            }
+            AnswerCitation::Spec {
+                category_name,
+                label_name,
+            } => {
+                let found = item.variants().iter().any(|(_, data)| {
+                    item.specs()
+                        .get_spec_entries(&data.spec_keys)
+                        .iter()
+                        .any(|entry| {
+                            entry.category_name == *category_name && entry.label_name == *label_name
+                        })
+                });
+                if !found {
+                    return Err((
+                        QaErrorCode::InvalidCitation,
+                        format!(
+                            "Spec {} / {} not found in item with id {}",
+                            category_name, label_name, item.id
+                        ),
+                    ));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn get_question(key: &QuestionKey) -> Result<Question, (QaErrorCode, String)> {
+    QUESTIONS.with_borrow(|p| p.get(key)).ok_or((
+        QaErrorCode::QuestionNotFound,
+        format!(
+            "Question {} of item with id {} not found",
+            key.question_id, key.item_id
+        ),
+    ))
+}
+
+fn item_questions(item_id: ItemId) -> Vec<(QuestionId, Question)> {
+    let start = QuestionKey {
+        item_id,
+        question_id: 0,
+    };
+
+    QUESTIONS.with_borrow(|p| {
+        p.range(start..)
+            .take_while(|(key, _)| key.item_id == item_id)
+            .map(|(key, question)| (key.question_id, question))
+            .collect()
+    })
+}
+
+/// Asks a question about an item. Questions are published once moderated.
+pub(crate) fn ask_question(
+    caller: Principal,
+    item_id: ItemId,
+    text: String,
+) -> Result<QuestionId, (QaErrorCode, String)> {
+    let author = auth::require_authenticated::<QaErrorCode>(caller)?;
+    validate_text(&text)?;
+    get_item(&item_id).map_err(|(_, message)| (QaErrorCode::ItemNotFound, message))?;
+
+    let pending = item_questions(item_id)
+        .iter()
+        .filter(|(_, question)| question.status == QaStatus::Pending)
+        .count();
+    if pending >= MAX_PENDING_QUESTIONS_PER_ITEM {
+        return Err((
+            QaErrorCode::TooManyPendingQuestions,
+            format!(
+                "Item with id {} has {} questions waiting for moderation",
+                item_id, pending
+            ),
+        ));
+    }
+
+    let question_id = QUESTIONS.with_borrow(|p| {
+        p.range(
+            QuestionKey {
+                item_id,
+                question_id: QuestionId::MIN,
+            }..=QuestionKey {
+                item_id,
+                question_id: QuestionId::MAX,
+            },
+        )
+        .next_back()
+        .map_or(0, |(key, _)| key.question_id + 1)
+    });
+    let question = Question {
+        author,
+        text,
+        status: QaStatus::Pending,
+        votes: 0,
+        answers: Vec::new(),
+        created_at: ic_cdk::api::time(),
+    };
+    QUESTIONS.with_borrow_mut(|p| {
+        p.insert(
+            QuestionKey {
+                item_id,
+                question_id,
+            },
+            question,
+        )
+    });
+
+    Ok(question_id)
+}
+
+/// Answers a question as staff, published right away, or as a verified buyer
+/// when the store allows it, published once moderated.
+pub(crate) fn answer_question(
+    caller: Principal,
+    key: QuestionKey,
+    mut input: AnswerInput,
+) -> Result<AnswerIndex, (QaErrorCode, String)> {
+    let author = auth::require_authenticated::<QaErrorCode>(caller)?;
+    let by_staff = auth::is_staff(&author);
+    let buyers_allowed = data::get_store_data()
+        .and_then(|data| data.verified_buyer_answers)
+        .unwrap_or(false);
+    if !by_staff && !(buyers_allowed && review::is_verified_buyer(key.item_id, author)) {
+        return Err((
+            QaErrorCode::Unauthorized,
+            format!(
+                "{} cannot answer questions about item with id {}",
+                author, key.item_id
+            ),
+        ));
+    }
+
+    validate_text(&input.text)?;
+    let item =
+        get_item(&key.item_id).map_err(|(_, message)| (QaErrorCode::ItemNotFound, message))?;
+    validate_citations(&item, &mut input.citations)?;
+
+    let mut question = get_question(&key)?;
+    let visible = question
+        .answers
+        .iter()
+        .filter(|answer| answer.status != QaStatus::Hidden)
+        .count();
+    if !by_staff && visible >= MAX_ANSWERS_PER_QUESTION {
+        return Err((
+            QaErrorCode::TooManyAnswers,
+            format!(
+                "A question can have at most {} answers that are not hidden",
+                MAX_ANSWERS_PER_QUESTION
+            ),
+        ));
+    }
+
+    question.answers.push(Answer {
+        author,
+        text: input.text,
+        citations: input.citations,
+        status: match by_staff {
+            true => QaStatus::Published,
+            false => QaStatus::Pending,
+        },
+        votes: 0,
+        by_staff,
+        created_at: ic_cdk::api::time(),
+    });
+    let index = (question.answers.len() - 1) as AnswerIndex;
+    QUESTIONS.with_borrow_mut(|p| p.insert(key, question));
+
+    Ok(index)
+}
+
+/// Publishes or hides a question, or one of its answers when `answer` is given.
+pub(crate) fn moderate_question(
+    caller: Principal,
+    key: QuestionKey,
+    answer: Option<AnswerIndex>,
+    action: QaModeration,
+) -> Result<(), (QaErrorCode, String)> {
+    auth::require_staff::<QaErrorCode>(caller)?;
+
+    let status = match action {
+        QaModeration::Publish => QaStatus::Published,
+        QaModeration::Hide => QaStatus::Hidden,
+    };
+    let mut question = get_question(&key)?;
+    match answer {
+        Some(index) => {
+            question
+                .answers
+                .get_mut(index as usize)
+                .ok_or((
+                    QaErrorCode::AnswerNotFound,
+                    format!("Answer {} of question {} not found", index, key.question_id),
+                ))?
+                .status = status
+        }
+        None => question.status = status,
+    }
+    QUESTIONS.with_borrow_mut(|p| p.insert(key, question));
+
+    Ok(())
+}
+
+/// Upvotes a published question, or one of its published answers when `answer` is given.
+pub(crate) fn upvote(
+    caller: Principal,
+    key: QuestionKey,
+    answer: Option<AnswerIndex>,
+) -> Result<u64, (QaErrorCode, String)> {
+    let voter = auth::require_authenticated::<QaErrorCode>(caller)?;
+
+    let mut question = get_question(&key)?;
+    if question.status != QaStatus::Published {
+        return Err((
+            QaErrorCode::QuestionNotFound,
+            format!("Question {} is not published", key.question_id),
+        ));
+    }
+    let votes = match answer {
+        Some(index) => match question.answers.get_mut(index as usize) {
+            Some(answer) if answer.status == QaStatus::Published => &mut answer.votes,
+            _ => {
+                return Err((
+                    QaErrorCode::AnswerNotFound,
+                    format!("Answer {} of question {} not found", index, key.question_id),
+                ))
+            }
+        },
+        None => &mut question.votes,
+    };
+
+    let vote_key = QaVoteKey {
+        question: key.clone(),
+        answer,
+        voter,
+    };
+    if QA_VOTES.with_borrow(|p| p.contains_key(&vote_key)) {
+        return Err((
+            QaErrorCode::AlreadyVoted,
+            format!("{} has already voted", voter),
+        ));
+    }
+    *votes += 1;
+    let votes = *votes;
+
+    QA_VOTES.with_borrow_mut(|p| p.insert(vote_key, ic_cdk::api::time()));
+    QUESTIONS.with_borrow_mut(|p| p.insert(key, question));
+
+    Ok(votes)
+}
+
+/// Lists the published questions of an item, most voted or newest first.
+pub(crate) fn list_item_questions(item_id: ItemId, query: QuestionQuery) -> QuestionPage {
+    let mut questions: Vec<(QuestionId, Question)> = item_questions(item_id)
+        .into_iter()
+        .filter(|(_, question)| question.status == QaStatus::Published)
+        .map(|(question_id, mut question)| {
+            question
+                .answers
+                .retain(|answer| answer.status == QaStatus::Published);
+            question.answers.sort_by(|a, b| b.votes.cmp(&a.votes));
+            (question_id, question)
+        })
+        .collect();
+    match query.sort {
+        QuestionSort::Votes => questions.sort_by(|(_, a), (_, b)| b.votes.cmp(&a.votes)),
+        QuestionSort::Recent => questions.sort_by(|(_, a), (_, b)| b.created_at.cmp(&a.created_at)),
+    }
+
+    QuestionPage {
+        total: questions.len() as u64,
+        questions: questions
+            .into_iter()
+            .skip(query.offset as usize)
+            .take(query.limit.min(MAX_QUESTION_LIMIT) as usize)
+            .collect(),
+    }
+}
+
+/// Lists the questions that are pending or have pending answers, oldest first.
+pub(crate) fn list_pending_questions(
+    caller: Principal,
+    offset: u64,
+    limit: u64,
+) -> Result<Vec<(QuestionKey, Question)>, (QaErrorCode, String)> {
+    auth::require_staff::<QaErrorCode>(caller)?;
+
+    let mut questions: Vec<(QuestionKey, Question)> = QUESTIONS.with_borrow(|p| {
+        p.iter()
+            .filter(|(_, question)| {
+                question.status == QaStatus::Pending
+                    || question
+                        .answers
+                        .iter()
+                        .any(|answer| answer.status == QaStatus::Pending)
+            })
+            .collect()
+    });
+    questions.sort_by_key(|(_, question)| question.created_at);
+
+    Ok(questions
+        .into_iter()
+        .skip(offset as usize)
+        .take(limit.min(MAX_QUESTION_LIMIT) as usize)
+        .collect())
+}
+
+pub(crate) fn update_verified_buyer_answers(
+    caller: Principal,
+    enabled: bool,
+) -> Result<(), (AuthErrorCode, String)> {
+    auth::require_owner::<AuthErrorCode>(caller)?;
+    let _ = data::update_verified_buyer_answers(enabled);
+
+    Ok(())
+}
@@ -0,0 +1,290 @@
+use super::{attr::AttrKeysV2, get_item, ItemId, ItemSummary};
+use crate::{
+    auth::{self, AuthErrorCode},
+    locale::LocaleChain,
+    ITEMS_IN_ID, ITEM_RELATIONS,
+};
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use common::unit::Currency;
+use ic_stable_structures::{storable::Bound, Storable};
+use serde::Serialize;
+use std::borrow::Cow;
+
+/// Largest number of relations stored under one key.
+pub const MAX_RELATIONS_PER_KEY: usize = 50;
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelationKind {
+    Related,
+    // The target is an accessory of the item
+    AccessoryOf,
+    // The target replaces the item, e.g. a newer model of a discontinued one
+    ReplacementFor,
+    UpgradeTo,
+    FrequentlyBoughtTogether,
+}
+
+/// Key of the relations of an item, or of one of its variants.
+/// Variant keys sort right after the key of their item.
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ItemRelationKey {
+    pub item_id: ItemId,
+    pub attr_keys: Option<AttrKeysV2>,
+}
+
+impl Storable for ItemRelationKey {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ItemRelation {
+    pub kind: RelationKind,
+    pub target: ItemId,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, Default)]
+pub struct ItemRelations(pub Vec<ItemRelation>);
+
+impl Storable for ItemRelations {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// A related item as shown on the page of another item.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct RelatedItem {
+    pub kind: RelationKind,
+    pub summary: ItemSummary,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum RelationErrorCode {
+    Unauthorized,
+    ItemNotFound,
+    VariantNotFound,
+    SelfRelation,
+    TooManyRelations,
+}
+
+impl From<AuthErrorCode> for RelationErrorCode {
+    fn from(code: AuthErrorCode) -> Self {
+        match code {
+            AuthErrorCode::Unauthorized => RelationErrorCode::Unauthorized,
+        }
+    }
+}
+
+fn item_exists(item_id: &ItemId) -> bool {
+    ITEMS_IN_ID.with_borrow(|p| p.contains_key(item_id))
+}
+
+/// Replaces the relations of an item, or of one of its variants when `attr_keys` is given.
+/// Duplicate relations are dropped, and an empty list removes the key.
+pub(crate) fn set_item_relations(
+    caller: Principal,
+    key: ItemRelationKey,
+    relations: Vec<ItemRelation>,
+) -> Result<(), (RelationErrorCode, String)> {
+    auth::require_staff::<RelationErrorCode>(caller)?;
+
+    let item = get_item(&key.item_id)
+        .map_err(|(_, message)| (RelationErrorCode::ItemNotFound, message))?;
+    let key = ItemRelationKey {
+        attr_keys: key
+            .attr_keys
+            .map(|attr_keys| attr_keys.normalized(item.dimensions())),
+        ..key
+    };
+    if let Some(attr_keys) = &key.attr_keys {
+        if item.get_variant(attr_keys).is_none() {
+            return Err((
+                RelationErrorCode::VariantNotFound,
+                format!(
+                    "Attribute with keys {:?} of item with id {} not found in the store",
+                    attr_keys, key.item_id
+                ),
+            ));
+        }
+    }
+
+    let mut deduped: Vec<ItemRelation> = Vec::new();
+    for relation in relations {
+        if relation.target == key.item_id {
+            return Err((
+                RelationErrorCode::SelfRelation,
+                format!("Item with id {} cannot be related to itself", key.item_id),
+            ));
+        }
+        if !item_exists(&relation.target) {
+            return Err((
+                RelationErrorCode::ItemNotFound,
+                format!("Item with id {} not found in the store", relation.target),
+            ));
+        }
+        if !deduped.contains(&relation) {
+            deduped.push(relation);
+        }
+    }
+    if deduped.len() > MAX_RELATIONS_PER_KEY {
+        return Err((
+            RelationErrorCode::TooManyRelations,
+            format!(
+                "At most {} relations can be set per item or variant",
+                MAX_RELATIONS_PER_KEY
+            ),
+        ));
+    }
+
+    ITEM_RELATIONS.with_borrow_mut(|p| match deduped.is_empty() {
+        true => p.remove(&key),
+        false => p.insert(key, ItemRelations(deduped)),
+    });
+
+    Ok(())
+}
+
+/// Lists the relations of an item and of each of its variants, the item-level ones first.
+pub(crate) fn list_item_relations(item_id: ItemId) -> Vec<(Option<AttrKeysV2>, Vec<ItemRelation>)> {
+    let start = ItemRelationKey {
+        item_id,
+        attr_keys: None,
+    };
+
+    ITEM_RELATIONS.with_borrow(|p| {
+        p.range(start..)
+            .take_while(|(key, _)| key.item_id == item_id)
+            .map(|(key, relations)| (key.attr_keys, relations.0))
+            .collect()
+    })
+}
+
+/// Summarizes the items related to an item and to the variant shown, item-level relations first.
+/// Links to items that no longer exist are skipped.
+pub(crate) fn get_related_items(
+    item_id: ItemId,
+    attr_keys: &AttrKeysV2,
+    currency: &Currency,
+    chain: &LocaleChain,
+) -> Vec<RelatedItem> {
+    let keys = [
+        ItemRelationKey {
+            item_id,
+            attr_keys: None,
+        },
+        ItemRelationKey {
+            item_id,
+            attr_keys: Some(attr_keys.clone()),
+        },
+    ];
+    let mut relations: Vec<ItemRelation> = Vec::new();
+    ITEM_RELATIONS.with_borrow(|p| {
+        for relation in keys.iter().filter_map(|key| p.get(key)).flat_map(|r| r.0) {
+            if !relations.contains(&relation) {
+                relations.push(relation);
+            }
+        }
+    });
+
+    relations
+        .into_iter()
+        .filter_map(|relation| {
+            let item = get_item(&relation.target).ok()?;
+            let mut summary = item.summary(Some(currency));
+            if let Some(name) = item
+                .translations
+                .as_ref()
+                .and_then(|translations| translations.name(chain))
+            {
+                summary.name = name.clone();
+            }
+            Some(RelatedItem {
+                kind: relation.kind,
+                summary,
+            })
+        })
+        .collect()
+}
+
+/// Normalizes the variant keys of the relations of an item to its current number of dimensions,
+/// after a change that drops unused trailing dimensions.
+pub(crate) fn normalize_relation_keys(item_id: &ItemId, dimensions: usize) {
+    let start = ItemRelationKey {
+        item_id: *item_id,
+        attr_keys: None,
+    };
+    let entries: Vec<(ItemRelationKey, ItemRelations)> = ITEM_RELATIONS.with_borrow(|p| {
+        p.range(start..)
+            .take_while(|(key, _)| key.item_id == *item_id)
+            .filter(|(key, _)| key.attr_keys.is_some())
+            .collect()
+    });
+
+    ITEM_RELATIONS.with_borrow_mut(|p| {
+        for (key, relations) in entries {
+            let normalized = ItemRelationKey {
+                item_id: key.item_id,
+                attr_keys: key
+                    .attr_keys
+                    .as_ref()
+                    .map(|attr_keys| attr_keys.normalized(dimensions)),
+            };
+            if normalized != key {
+                p.remove(&key);
+                p.insert(normalized, relations);
+            }
+        }
+    });
+}
+
+/// Removes the links to items that no longer exist, and the relations of such items.
+/// Returns the number of keys that were changed or removed.
+pub(crate) fn prune_dead_links() -> u64 {
+    let entries: Vec<(ItemRelationKey, ItemRelations)> =
+        ITEM_RELATIONS.with_borrow(|p| p.iter().collect());
+    let mut changed = 0;
+
+    ITEM_RELATIONS.with_borrow_mut(|p| {
+        for (key, relations) in entries {
+            let before = relations.0.len();
+            let kept: Vec<ItemRelation> = match item_exists(&key.item_id) {
+                true => relations
+                    .0
+                    .into_iter()
+                    .filter(|relation| item_exists(&relation.target))
+                    .collect(),
+                false => Vec::new(),
+            };
+            if kept.len() == before {
+                continue;
+            }
+            changed += 1;
+            match kept.is_empty() {
+                true => p.remove(&key),
+                false => p.insert(key, ItemRelations(kept)),
+            };
+        }
+    });
+
+    changed
+}
+
+pub(crate) fn prune_item_relations(caller: Principal) -> Result<u64, (RelationErrorCode, String)> {
+    auth::require_staff::<RelationErrorCode>(caller)?;
+
+    Ok(prune_dead_links())
+}
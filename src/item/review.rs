@@ -0,0 +1,424 @@
+use super::{attr::AttrKeysV2, get_item, ItemId};
+use crate::{
+    auth::{self, AuthErrorCode},
+    media::{self, MediaId},
+    ITEM_RATINGS, REVIEWS,
+};
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{storable::Bound, Storable};
+use serde::Serialize;
+use std::borrow::Cow;
+
+pub const MAX_RATING: u8 = 5;
+pub const MAX_REVIEW_TEXT_LENGTH: usize = 5000;
+pub const MAX_REVIEW_MEDIA: usize = 10;
+/// Largest number of reviews of an item waiting for moderation. New reviews are rejected
+/// until the owner catches up, so unmoderated posts cannot grow without bound.
+pub const MAX_PENDING_REVIEWS_PER_ITEM: usize = 200;
+/// Largest page of reviews returned by one query.
+pub const MAX_REVIEW_LIMIT: u64 = 50;
+
+/// Key of a review. A principal has at most one review per item,
+/// and the reviews of an item are adjacent.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ReviewKey {
+    pub item_id: ItemId,
+    pub author: Principal,
+}
+
+impl Storable for ReviewKey {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReviewStatus {
+    // Waiting for moderation, only visible to its author and the owner
+    Pending,
+    Approved,
+    Hidden,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct ReviewReply {
+    pub text: String,
+    pub replied_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct Review {
+    pub rating: u8,
+    pub text: String,
+    // Variant the review is about, if any
+    pub attr_keys: Option<AttrKeysV2>,
+    pub media_ids: Vec<MediaId>,
+    pub status: ReviewStatus,
+    // Set by the owner while the store has no orders to check purchases against
+    pub verified_purchase: bool,
+    pub reply: Option<ReviewReply>,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+impl Storable for Review {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct ReviewInput {
+    pub rating: u8,
+    pub text: String,
+    pub attr_keys: Option<AttrKeysV2>,
+    // Entries of the store media library. Only staff can add to the library, so reviewers
+    // can attach media staff added for them but can't upload their own photos.
+    pub media_ids: Vec<MediaId>,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub enum ReviewModeration {
+    Approve,
+    Hide,
+    Reply(String),
+    RemoveReply,
+    VerifyPurchase(bool),
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct ReviewQuery {
+    pub offset: u64,
+    pub limit: u64,
+    // Only reviews with this rating
+    pub rating: Option<u8>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct ReviewPage {
+    pub reviews: Vec<(Principal, Review)>,
+    pub total: u64,
+}
+
+/// Ratings of the approved reviews of an item.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Default)]
+pub struct RatingSummary {
+    pub count: u64,
+    pub average: f64,
+    // Number of reviews with each rating, from one star up
+    pub histogram: Vec<u64>,
+}
+
+impl Storable for RatingSummary {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum ReviewErrorCode {
+    Unauthorized,
+    ItemNotFound,
+    VariantNotFound,
+    MediaNotFound,
+    ReviewNotFound,
+    InvalidReview,
+    TooManyPendingReviews,
+}
+
+impl From<AuthErrorCode> for ReviewErrorCode {
+    fn from(code: AuthErrorCode) -> Self {
+        match code {
+            AuthErrorCode::Unauthorized => ReviewErrorCode::Unauthorized,
+        }
+    }
+}
+
+fn item_reviews(item_id: ItemId) -> Vec<(Principal, Review)> {
+    // The management canister id is the empty principal, which sorts first
+    let start = ReviewKey {
+        item_id,
+        author: Principal::management_canister(),
+    };
+
+    REVIEWS.with_borrow(|p| {
+        p.range(start..)
+            .take_while(|(key, _)| key.item_id == item_id)
+            .map(|(key, review)| (key.author, review))
+            .collect()
+    })
+}
+
+/// Recomputes the rating summary of an item from its approved reviews.
+pub(crate) fn update_rating_summary(item_id: ItemId) {
+    let mut summary = RatingSummary {
+        histogram: vec![0; MAX_RATING as usize],
+        ..Default::default()
+    };
+    let mut total = 0;
+    for (_, review) in item_reviews(item_id) {
+        if review.status != ReviewStatus::Approved {
+            continue;
+        }
+        summary.count += 1;
+        summary.histogram[review.rating as usize - 1] += 1;
+        total += review.rating as u64;
+    }
+
+    ITEM_RATINGS.with_borrow_mut(|p| match summary.count {
+        0 => p.remove(&item_id),
+        count => {
+            summary.average = total as f64 / count as f64;
+            p.insert(item_id, summary)
+        }
+    });
+}
+
+/// Whether the principal has a review of the item with a verified purchase.
+pub(crate) fn is_verified_buyer(item_id: ItemId, principal: Principal) -> bool {
+    REVIEWS.with_borrow(|p| {
+        p.get(&ReviewKey {
+            item_id,
+            author: principal,
+        })
+        .is_some_and(|review| review.verified_purchase)
+    })
+}
+
+pub(crate) fn get_rating_summary(item_id: &ItemId) -> Option<RatingSummary> {
+    ITEM_RATINGS.with_borrow(|p| p.get(item_id))
+}
+
+fn validate_review(
+    item_id: &ItemId,
+    input: &mut ReviewInput,
+) -> Result<(), (ReviewErrorCode, String)> {
+    let invalid = |message: String| Err((ReviewErrorCode::InvalidReview, message));
+
+    if !(1..=MAX_RATING).contains(&input.rating) {
+        return invalid(format!("Rating must be between 1 and {}", MAX_RATING));
+    }
+    if input.text.chars().count() > MAX_REVIEW_TEXT_LENGTH {
+        return invalid(format!(
+            "Review text can be at most {} characters",
+            MAX_REVIEW_TEXT_LENGTH
+        ));
+    }
+    if input.media_ids.len() > MAX_REVIEW_MEDIA {
+        return invalid(format!(
+            "A review can have at most {} media",
+            MAX_REVIEW_MEDIA
+        ));
+    }
+    if let Some(id) = input
+        .media_ids
+        .iter()
+        .find(|id| !media::contains_media(**id))
+    {
+        return Err((
+            ReviewErrorCode::MediaNotFound,
+            format!(
+                "Media with id {} not found in the media library, where only staff can add media",
+                id
+            ),
+        ));
+    }
+
+    let item =
+        get_item(item_id).map_err(|(_, message)| (ReviewErrorCode::ItemNotFound, message))?;
+    if let Some(attr_keys) = input.attr_keys.as_mut() {
+        *attr_keys = attr_keys.normalized(item.dimensions());
+        if item.get_variant(attr_keys).is_none() {
+            return Err((
+                ReviewErrorCode::VariantNotFound,
+                format!(
+                    "Attribute with keys {:?} of item with id {} not found in the store",
+                    attr_keys, item_id
+                ),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Posts the review of the caller, or replaces it. A replaced review waits for moderation again,
+/// keeping the reply and the verified purchase flag.
+pub(crate) fn post_review(
+    caller: Principal,
+    item_id: ItemId,
+    mut input: ReviewInput,
+) -> Result<(), (ReviewErrorCode, String)> {
+    let author = auth::require_authenticated::<ReviewErrorCode>(caller)?;
+    validate_review(&item_id, &mut input)?;
+
+    let key = ReviewKey { item_id, author };
+    let now = ic_cdk::api::time();
+    let prev = REVIEWS.with_borrow(|p| p.get(&key));
+    if prev.is_none() {
+        let pending = item_reviews(item_id)
+            .iter()
+            .filter(|(_, review)| review.status == ReviewStatus::Pending)
+            .count();
+        if pending >= MAX_PENDING_REVIEWS_PER_ITEM {
+            return Err((
+                ReviewErrorCode::TooManyPendingReviews,
+                format!(
+                    "Item with id {} has {} reviews waiting for moderation",
+                    item_id, pending
+                ),
+            ));
+        }
+    }
+
+    let review = Review {
+        rating: input.rating,
+        text: input.text,
+        attr_keys: input.attr_keys,
+        media_ids: input.media_ids,
+        status: ReviewStatus::Pending,
+        verified_purchase: prev.as_ref().is_some_and(|prev| prev.verified_purchase),
+        reply: prev.as_ref().and_then(|prev| prev.reply.clone()),
+        created_at: prev.as_ref().map_or(now, |prev| prev.created_at),
+        updated_at: now,
+    };
+
+    REVIEWS.with_borrow_mut(|p| p.insert(key, review));
+    update_rating_summary(item_id);
+
+    Ok(())
+}
+
+/// Deletes the review of the caller.
+pub(crate) fn delete_review(
+    caller: Principal,
+    item_id: ItemId,
+) -> Result<(), (ReviewErrorCode, String)> {
+    let author = auth::require_authenticated::<ReviewErrorCode>(caller)?;
+
+    REVIEWS
+        .with_borrow_mut(|p| p.remove(&ReviewKey { item_id, author }))
+        .ok_or((
+            ReviewErrorCode::ReviewNotFound,
+            format!("{} has no review of item with id {}", author, item_id),
+        ))?;
+    update_rating_summary(item_id);
+
+    Ok(())
+}
+
+pub(crate) fn moderate_review(
+    caller: Principal,
+    key: ReviewKey,
+    action: ReviewModeration,
+) -> Result<(), (ReviewErrorCode, String)> {
+    auth::require_owner::<ReviewErrorCode>(caller)?;
+
+    let mut review = REVIEWS.with_borrow(|p| p.get(&key)).ok_or((
+        ReviewErrorCode::ReviewNotFound,
+        format!(
+            "{} has no review of item with id {}",
+            key.author, key.item_id
+        ),
+    ))?;
+
+    match action {
+        ReviewModeration::Approve => review.status = ReviewStatus::Approved,
+        ReviewModeration::Hide => review.status = ReviewStatus::Hidden,
+        ReviewModeration::Reply(text) => {
+            if text.chars().count() > MAX_REVIEW_TEXT_LENGTH {
+                return Err((
+                    ReviewErrorCode::InvalidReview,
+                    format!(
+                        "Reply text can be at most {} characters",
+                        MAX_REVIEW_TEXT_LENGTH
+                    ),
+                ));
+            }
+            review.reply = Some(ReviewReply {
+                text,
+                replied_at: ic_cdk::api::time(),
+            });
+        }
+        ReviewModeration::RemoveReply => review.reply = None,
+        ReviewModeration::VerifyPurchase(verified) => review.verified_purchase = verified,
+    }
+
+    let item_id = key.item_id;
+    REVIEWS.with_borrow_mut(|p| p.insert(key, review));
+    update_rating_summary(item_id);
+
+    Ok(())
+}
+
+/// Lists the approved reviews of an item, newest first.
+pub(crate) fn list_item_reviews(item_id: ItemId, query: ReviewQuery) -> ReviewPage {
+    let mut reviews: Vec<(Principal, Review)> = item_reviews(item_id)
+        .into_iter()
+        .filter(|(_, review)| review.status == ReviewStatus::Approved)
+        .filter(|(_, review)| match query.rating {
+            Some(rating) => review.rating == rating,
+            None => true,
+        })
+        .collect();
+    reviews.sort_by(|(_, a), (_, b)| b.created_at.cmp(&a.created_at));
+
+    ReviewPage {
+        total: reviews.len() as u64,
+        reviews: reviews
+            .into_iter()
+            .skip(query.offset as usize)
+            .take(query.limit.min(MAX_REVIEW_LIMIT) as usize)
+            .collect(),
+    }
+}
+
+/// Returns the review of the caller whatever its status, so authors can see pending reviews.
+pub(crate) fn get_own_review(
+    caller: Principal,
+    item_id: ItemId,
+) -> Result<Option<Review>, (ReviewErrorCode, String)> {
+    let author = auth::require_authenticated::<ReviewErrorCode>(caller)?;
+
+    Ok(REVIEWS.with_borrow(|p| p.get(&ReviewKey { item_id, author })))
+}
+
+/// Lists the reviews waiting for moderation across the store, oldest first.
+pub(crate) fn list_pending_reviews(
+    caller: Principal,
+    offset: u64,
+    limit: u64,
+) -> Result<Vec<(ReviewKey, Review)>, (ReviewErrorCode, String)> {
+    auth::require_owner::<ReviewErrorCode>(caller)?;
+
+    let mut reviews: Vec<(ReviewKey, Review)> = REVIEWS.with_borrow(|p| {
+        p.iter()
+            .filter(|(_, review)| review.status == ReviewStatus::Pending)
+            .collect()
+    });
+    reviews.sort_by_key(|(_, review)| review.updated_at);
+
+    Ok(reviews
+        .into_iter()
+        .skip(offset as usize)
+        .take(limit.min(MAX_REVIEW_LIMIT) as usize)
+        .collect())
+}
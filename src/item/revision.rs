@@ -0,0 +1,363 @@
+use super::{
+    attr::{AttrKeysV2, AttrSpecificData},
+    image::ImageVecKey,
+    Item, ItemId, ItemName, ItemUpdateErrorCode,
+};
+use crate::{auth, ITEM_REVISIONS};
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use common::{
+    item::attr::Stock,
+    unit::{Currency, Price},
+};
+use ic_stable_structures::{storable::Bound, Storable};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
+};
+
+pub type RevisionNumber = u32;
+
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ItemRevisionKey {
+    pub item_id: ItemId,
+    pub revision: RevisionNumber,
+}
+
+impl Storable for ItemRevisionKey {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub enum ItemRevisionReason {
+    Replaced,
+    Patched,
+    RolledBack { to: RevisionNumber },
+}
+
+/// A previous state of an item, kept whenever the item is replaced or patched.
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct ItemRevision {
+    pub item: Item,
+    pub author: Principal,
+    pub timestamp: u64,
+    pub reason: ItemRevisionReason,
+}
+
+impl Storable for ItemRevision {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct ItemRevisionSummary {
+    pub revision: RevisionNumber,
+    pub name: ItemName,
+    pub author: Principal,
+    pub timestamp: u64,
+    pub reason: ItemRevisionReason,
+}
+
+/// Structural difference between two states of an item.
+/// Every field is empty (or `None`) when that part of the item is unchanged.
+#[derive(CandidType, Deserialize, Debug, Clone, Default)]
+pub struct ItemRevisionDiff {
+    pub name: Option<(ItemName, ItemName)>,
+    pub descriptions: Option<(Vec<String>, Vec<String>)>,
+    pub attr_indexes_changed: bool,
+    pub attrs: Vec<AttrDiff>,
+    pub images: Vec<ImageGroupDiff>,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct AttrDiff {
+    pub keys: AttrKeysV2,
+    pub change: AttrDiffKind,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub enum AttrDiffKind {
+    Added,
+    Removed,
+    Modified {
+        stock: Option<(Stock, Stock)>,
+        prices: Vec<PriceDiff>,
+        image_vec_key: Option<(ImageVecKey, ImageVecKey)>,
+        spec_keys_changed: bool,
+    },
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct PriceDiff {
+    pub currency: Currency,
+    pub before: Option<Price>,
+    pub after: Option<Price>,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct ImageGroupDiff {
+    pub key: ImageVecKey,
+    pub change: ImageGroupDiffKind,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub enum ImageGroupDiffKind {
+    Added,
+    Removed,
+    Modified,
+}
+
+/// Selects one side of a diff: a stored revision, or the live item when `None`.
+pub type RevisionSelector = Option<RevisionNumber>;
+
+/// Stores `item` as the next revision of its id and returns the revision number.
+pub(crate) fn record_revision(
+    item: Item,
+    author: Principal,
+    reason: ItemRevisionReason,
+) -> RevisionNumber {
+    let item_id = item.id;
+    let revision = latest_revision(&item_id).map_or(0, |r| r + 1);
+
+    let entry = ItemRevision {
+        item,
+        author,
+        timestamp: ic_cdk::api::time(),
+        reason,
+    };
+
+    ITEM_REVISIONS.with_borrow_mut(|p| p.insert(ItemRevisionKey { item_id, revision }, entry));
+
+    revision
+}
+
+fn latest_revision(item_id: &ItemId) -> Option<RevisionNumber> {
+    ITEM_REVISIONS.with_borrow(|p| {
+        p.range(revision_range(item_id))
+            .map(|(key, _)| key.revision)
+            .last()
+    })
+}
+
+fn revision_range(item_id: &ItemId) -> std::ops::RangeInclusive<ItemRevisionKey> {
+    ItemRevisionKey {
+        item_id: *item_id,
+        revision: RevisionNumber::MIN,
+    }..=ItemRevisionKey {
+        item_id: *item_id,
+        revision: RevisionNumber::MAX,
+    }
+}
+
+pub(crate) fn list_item_revisions(item_id: &ItemId) -> Vec<ItemRevisionSummary> {
+    ITEM_REVISIONS.with_borrow(|p| {
+        p.range(revision_range(item_id))
+            .map(|(key, entry)| ItemRevisionSummary {
+                revision: key.revision,
+                name: entry.item.name.clone(),
+                author: entry.author,
+                timestamp: entry.timestamp,
+                reason: entry.reason,
+            })
+            .collect()
+    })
+}
+
+pub(crate) fn get_item_revision(
+    item_id: &ItemId,
+    revision: RevisionNumber,
+) -> Option<ItemRevision> {
+    ITEM_REVISIONS.with_borrow(|p| {
+        p.get(&ItemRevisionKey {
+            item_id: *item_id,
+            revision,
+        })
+    })
+}
+
+fn get_selected_item(
+    item_id: &ItemId,
+    selector: RevisionSelector,
+) -> Result<Item, (ItemUpdateErrorCode, String)> {
+    match selector {
+        Some(revision) => get_item_revision(item_id, revision)
+            .map(|entry| entry.item)
+            .ok_or((
+                ItemUpdateErrorCode::RevisionNotFound,
+                format!(
+                    "Revision {} of item with id {} not found",
+                    revision, item_id
+                ),
+            )),
+        None => super::get_item(item_id),
+    }
+}
+
+pub(crate) fn diff_item_revisions(
+    item_id: &ItemId,
+    from: RevisionSelector,
+    to: RevisionSelector,
+) -> Result<ItemRevisionDiff, (ItemUpdateErrorCode, String)> {
+    let before = get_selected_item(item_id, from)?;
+    let after = get_selected_item(item_id, to)?;
+
+    Ok(diff_items(&before, &after))
+}
+
+/// Compares two items field by field.
+pub fn diff_items(before: &Item, after: &Item) -> ItemRevisionDiff {
+    let mut diff = ItemRevisionDiff::default();
+
+    if !candid_eq(&before.name, &after.name) {
+        diff.name = Some((before.name.clone(), after.name.clone()));
+    }
+
+    if before.descriptions() != after.descriptions() {
+        diff.descriptions = Some((before.descriptions().clone(), after.descriptions().clone()));
+    }
+
+    diff.attr_indexes_changed = !candid_eq(
+        &before.get_attrs_indexes_result(),
+        &after.get_attrs_indexes_result(),
+    );
+    diff.attrs = diff_attrs(
+        &before.variants().into_iter().collect(),
+        &after.variants().into_iter().collect(),
+    );
+
+    let (before_images, after_images) = (before.images(), after.images());
+
+    let group_keys = before_images.group_keys();
+    for key in group_keys.union(&after_images.group_keys()) {
+        let change = match (
+            before_images.get_index_vec(key),
+            after_images.get_index_vec(key),
+        ) {
+            (Some(_), None) => ImageGroupDiffKind::Removed,
+            (None, Some(_)) => ImageGroupDiffKind::Added,
+            (Some(before_group), Some(after_group)) if !candid_eq(&before_group, &after_group) => {
+                ImageGroupDiffKind::Modified
+            }
+            _ => continue,
+        };
+        diff.images.push(ImageGroupDiff { key: *key, change });
+    }
+
+    diff
+}
+
+fn diff_attrs(
+    before: &BTreeMap<AttrKeysV2, &AttrSpecificData>,
+    after: &BTreeMap<AttrKeysV2, &AttrSpecificData>,
+) -> Vec<AttrDiff> {
+    let mut result = Vec::new();
+
+    for (keys, before_data) in before {
+        let Some(after_data) = after.get(keys) else {
+            result.push(AttrDiff {
+                keys: keys.clone(),
+                change: AttrDiffKind::Removed,
+            });
+            continue;
+        };
+
+        let stock = (before_data.stock != after_data.stock)
+            .then_some((before_data.stock, after_data.stock));
+
+        let currencies: BTreeSet<&Currency> = before_data
+            .price
+            .keys()
+            .chain(after_data.price.keys())
+            .collect();
+
+        let mut prices = Vec::new();
+        for currency in currencies {
+            let before_price = before_data.price.get(currency).copied();
+            let after_price = after_data.price.get(currency).copied();
+            if !candid_eq(&before_price, &after_price) {
+                prices.push(PriceDiff {
+                    currency: currency.clone(),
+                    before: before_price,
+                    after: after_price,
+                });
+            }
+        }
+
+        let image_vec_key = (before_data.image_vec_key != after_data.image_vec_key)
+            .then_some((before_data.image_vec_key, after_data.image_vec_key));
+        let spec_keys_changed = before_data.spec_keys != after_data.spec_keys;
+
+        if stock.is_some() || !prices.is_empty() || image_vec_key.is_some() || spec_keys_changed {
+            result.push(AttrDiff {
+                keys: keys.clone(),
+                change: AttrDiffKind::Modified {
+                    stock,
+                    prices,
+                    image_vec_key,
+                    spec_keys_changed,
+                },
+            });
+        }
+    }
+
+    for keys in after.keys() {
+        if !before.contains_key(keys) {
+            result.push(AttrDiff {
+                keys: keys.clone(),
+                change: AttrDiffKind::Added,
+            });
+        }
+    }
+
+    result
+}
+
+/// Compares two values by their Candid encoding,
+/// for types from `common` that do not implement `PartialEq`.
+pub(crate) fn candid_eq<T: CandidType>(a: &T, b: &T) -> bool {
+    Encode!(a).ok() == Encode!(b).ok()
+}
+
+/// Restores a stored revision as the live item.
+/// The item being replaced is itself kept as a new revision, so a rollback can be undone.
+pub(crate) fn rollback_item(
+    author: Principal,
+    item_id: &ItemId,
+    revision: RevisionNumber,
+) -> Result<RevisionNumber, (ItemUpdateErrorCode, String)> {
+    let author = auth::require_staff::<ItemUpdateErrorCode>(author)?;
+
+    let entry = get_item_revision(item_id, revision).ok_or((
+        ItemUpdateErrorCode::RevisionNotFound,
+        format!(
+            "Revision {} of item with id {} not found",
+            revision, item_id
+        ),
+    ))?;
+
+    let (_, saved) = super::modify_item_as(
+        author,
+        item_id,
+        ItemRevisionReason::RolledBack { to: revision },
+        |item| {
+            *item = entry.item;
+            Ok(())
+        },
+    )?;
+
+    Ok(saved)
+}
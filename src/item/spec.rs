@@ -1,6 +1,14 @@
-use candid::{CandidType, Deserialize};
+use crate::spec_template::{self, SpecTemplateId};
+use candid::{CandidType, Decode, Deserialize, Encode};
 use common::item::spec::SpecValue;
-use std::collections::BTreeMap;
+use ic_stable_structures::{storable::Bound, Storable};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
+};
+
+pub mod unit;
+use unit::{TypedSpecValue, UnitSystem};
 
 type SpecCategoryKey = u8;
 #[derive(CandidType, Deserialize, Debug, Clone)]
@@ -9,6 +17,18 @@ pub struct SpecCategory {
     label_map: BTreeMap<SpecLabelKey, SpecLabel>,
 }
 
+impl Storable for SpecCategory {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
 impl SpecCategory {
     pub fn builder(name: &str) -> SpecCategoryBuilder {
         SpecCategoryBuilder::new(name)
@@ -50,6 +70,8 @@ type SpecLabelKey = u8;
 pub struct SpecLabel {
     name: String,
     value_map: BTreeMap<SpecValueKey, SpecValue>,
+    // Values that are shown from their typed form instead of `value_map`
+    typed_value_map: Option<BTreeMap<SpecValueKey, TypedSpecValue>>,
 }
 
 impl SpecLabel {
@@ -60,11 +82,33 @@ impl SpecLabel {
     pub fn get_value(&self, key: &SpecValueKey) -> Option<&common::item::spec::SpecValue> {
         self.value_map.get(key)
     }
+
+    pub fn get_typed_value(&self, key: &SpecValueKey) -> Option<&TypedSpecValue> {
+        self.typed_value_map.as_ref()?.get(key)
+    }
+
+    /// Renders a value, converting typed values to `unit_system` when one is given.
+    pub fn render_value(
+        &self,
+        key: &SpecValueKey,
+        unit_system: Option<UnitSystem>,
+    ) -> Option<SpecValue> {
+        let Some(typed) = self.get_typed_value(key) else {
+            return self.get_value(key).cloned();
+        };
+
+        let rendered = match unit_system {
+            Some(system) => typed.in_system(system).render(),
+            None => typed.render(),
+        };
+        Some(std::iter::once(rendered).collect())
+    }
 }
 
 pub struct SpecLabelBuilder {
     name: String,
     value_map: BTreeMap<SpecValueKey, SpecValue>,
+    typed_value_map: BTreeMap<SpecValueKey, TypedSpecValue>,
 }
 
 impl SpecLabelBuilder {
@@ -72,6 +116,7 @@ impl SpecLabelBuilder {
         Self {
             name: name.to_string(),
             value_map: BTreeMap::new(),
+            typed_value_map: BTreeMap::new(),
         }
     }
 
@@ -82,10 +127,20 @@ impl SpecLabelBuilder {
         self
     }
 
+    /// Add a typed value, which can be compared with other values and converted
+    /// to another unit system. Its rendered form is also kept in `value_map`.
+    pub fn typed_value(mut self, key: SpecValueKey, value: TypedSpecValue) -> Self {
+        self.value_map
+            .insert(key, std::iter::once(value.render()).collect());
+        self.typed_value_map.insert(key, value);
+        self
+    }
+
     pub fn build(self) -> SpecLabel {
         SpecLabel {
             name: self.name,
             value_map: self.value_map,
+            typed_value_map: (!self.typed_value_map.is_empty()).then_some(self.typed_value_map),
         }
     }
 }
@@ -147,12 +202,25 @@ impl SpecKeyLabel {
     }
 }
 
+/// A single value of a spec, as indexed for filtering.
+#[derive(Debug, Clone)]
+pub struct SpecEntry {
+    pub category_name: String,
+    pub label_name: String,
+    pub value: String,
+    pub typed: Option<TypedSpecValue>,
+}
+
 #[derive(CandidType, Deserialize, Debug, Clone)]
 pub struct ItemSpecsV1 {
     // Actual data of specs
     pub map: BTreeMap<SpecCategoryKey, SpecCategory>,
     // Mapping of combination of specs
     pub index_map: BTreeMap<SpecIndexKey, SpecKey>,
+    // Categories defined by store-level templates instead of `map`
+    pub template_map: Option<BTreeMap<SpecCategoryKey, SpecTemplateId>>,
+    // Specs shared by every variant, which the `spec_keys` of a variant add to or override
+    pub base_keys: Option<Vec<SpecIndexKey>>,
 }
 
 impl ItemSpecsV1 {
@@ -161,19 +229,31 @@ impl ItemSpecsV1 {
     }
 
     pub fn get_specs(&self, keys: &Vec<SpecIndexKey>) -> Option<common::item::spec::SpecResponse> {
+        self.get_specs_in(keys, None)
+    }
+
+    /// Same as `get_specs`, with typed values rendered in `unit_system` when one is given.
+    pub fn get_specs_in(
+        &self,
+        keys: &Vec<SpecIndexKey>,
+        unit_system: Option<UnitSystem>,
+    ) -> Option<common::item::spec::SpecResponse> {
+        if keys.iter().any(|key| self.get_spec_key(key).is_none()) {
+            return None;
+        }
+
         let mut result = Vec::new();
 
-        for key in keys {
+        for spec_key in self.merged_keys(keys) {
             let mut label_vec = Vec::new();
-            let spec_key = self.get_spec_key(key)?;
 
             let category = self.get_category(spec_key.category_key)?;
             for spec_key_label in &spec_key.label_vec {
                 let label = category.get_label(spec_key_label.label_key)?;
-                let value = label.get_value(&spec_key_label.value_key)?;
+                let value = label.render_value(&spec_key_label.value_key, unit_system)?;
                 label_vec.push(common::item::spec::SpecResponseLabel {
                     label_name: label.name.clone(),
-                    value: value.clone(),
+                    value,
                 });
             }
 
@@ -186,12 +266,146 @@ impl ItemSpecsV1 {
         Some(result)
     }
 
+    /// Flattens the specs of the given keys into one entry per value string,
+    /// with the typed value it was rendered from, if any. Missing keys are skipped.
+    pub fn get_spec_entries(&self, keys: &[SpecIndexKey]) -> Vec<SpecEntry> {
+        let mut result = Vec::new();
+
+        for spec_key in self.merged_keys(keys) {
+            let Some(category) = self.get_category(spec_key.category_key) else {
+                continue;
+            };
+            for spec_key_label in &spec_key.label_vec {
+                let Some(label) = category.get_label(spec_key_label.label_key) else {
+                    continue;
+                };
+                let typed = label.get_typed_value(&spec_key_label.value_key);
+                for value in label
+                    .get_value(&spec_key_label.value_key)
+                    .into_iter()
+                    .flatten()
+                {
+                    result.push(SpecEntry {
+                        category_name: category.name.clone(),
+                        label_name: label.name.clone(),
+                        value: value.clone(),
+                        typed: typed.cloned(),
+                    });
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Merges the spec keys of a variant onto the base specs of the item.
+    ///
+    /// A label of a base category is overridden by the variant when it sets the same label,
+    /// and added to the category otherwise. Categories the base does not have are kept as given.
+    /// Missing keys are skipped.
+    fn merged_keys(&self, keys: &[SpecIndexKey]) -> Vec<SpecKey> {
+        let base_keys = self.base_keys.as_deref().unwrap_or_default();
+        let mut merged: Vec<SpecKey> = base_keys
+            .iter()
+            .filter_map(|key| self.get_spec_key(key))
+            .cloned()
+            .collect();
+        let base_len = merged.len();
+
+        for spec_key in keys.iter().filter_map(|key| self.get_spec_key(key)) {
+            let base = merged[..base_len]
+                .iter_mut()
+                .find(|base| base.category_key == spec_key.category_key);
+            let Some(base) = base else {
+                merged.push(spec_key.clone());
+                continue;
+            };
+            for spec_key_label in &spec_key.label_vec {
+                match base
+                    .label_vec
+                    .iter_mut()
+                    .find(|label| label.label_key == spec_key_label.label_key)
+                {
+                    Some(label) => label.value_key = spec_key_label.value_key,
+                    None => base.label_vec.push(spec_key_label.clone()),
+                }
+            }
+        }
+
+        merged
+    }
+
+    /// Lists the labels whose value is not the same for every given variant,
+    /// including labels that only some of the variants have.
+    fn differing_labels(
+        &self,
+        variant_keys: &[&Vec<SpecIndexKey>],
+    ) -> BTreeSet<(SpecCategoryKey, SpecLabelKey)> {
+        let values: Vec<BTreeMap<(SpecCategoryKey, SpecLabelKey), SpecValueKey>> = variant_keys
+            .iter()
+            .map(|keys| {
+                self.merged_keys(keys)
+                    .iter()
+                    .flat_map(|spec_key| {
+                        spec_key.label_vec.iter().map(|label| {
+                            ((spec_key.category_key, label.label_key), label.value_key)
+                        })
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let labels: BTreeSet<_> = values.iter().flat_map(|map| map.keys().copied()).collect();
+        labels
+            .into_iter()
+            .filter(|label| {
+                let first = values.first().and_then(|map| map.get(label));
+                values.iter().any(|map| map.get(label) != first)
+            })
+            .collect()
+    }
+
+    /// Marks the values of `get_specs` for `keys` that differ between the given variants,
+    /// with one flag per label in the same order, so the UI can highlight them.
+    pub fn mark_differences(
+        &self,
+        keys: &[SpecIndexKey],
+        variant_keys: &[&Vec<SpecIndexKey>],
+    ) -> Vec<Vec<bool>> {
+        let differing = self.differing_labels(variant_keys);
+        self.merged_keys(keys)
+            .iter()
+            .map(|spec_key| {
+                spec_key
+                    .label_vec
+                    .iter()
+                    .map(|label| differing.contains(&(spec_key.category_key, label.label_key)))
+                    .collect()
+            })
+            .collect()
+    }
+
     fn get_spec_key(&self, key: &SpecIndexKey) -> Option<&SpecKey> {
         self.index_map.get(key)
     }
 
-    fn get_category(&self, key: SpecCategoryKey) -> Option<&SpecCategory> {
-        self.map.get(&key)
+    /// Returns the category of the item, or the template it references.
+    fn get_category(&self, key: SpecCategoryKey) -> Option<Cow<SpecCategory>> {
+        if let Some(category) = self.map.get(&key) {
+            return Some(Cow::Borrowed(category));
+        }
+
+        let template_id = self.template_map.as_ref()?.get(&key)?;
+        spec_template::get_spec_template(*template_id).map(Cow::Owned)
+    }
+
+    /// Lists the templates referenced by the item.
+    pub fn template_ids(&self) -> Vec<SpecTemplateId> {
+        self.template_map
+            .as_ref()
+            .map_or(Vec::new(), |template_map| {
+                template_map.values().copied().collect()
+            })
     }
 }
 
@@ -199,6 +413,8 @@ impl ItemSpecsV1 {
 pub struct ItemSpecsV1Builder {
     pub map: BTreeMap<SpecCategoryKey, SpecCategory>,
     pub index_map: BTreeMap<SpecIndexKey, SpecKey>,
+    pub template_map: BTreeMap<SpecCategoryKey, SpecTemplateId>,
+    pub base_keys: Vec<SpecIndexKey>,
 }
 
 impl ItemSpecsV1Builder {
@@ -226,10 +442,31 @@ impl ItemSpecsV1Builder {
         self
     }
 
+    /// Add a spec category defined by a store-level template.
+    ///
+    /// The key is used to reference the category, just like the key of `spec`.
+    /// Only the label and value keys are stored by the item,
+    /// so changes to the template apply to every item that references it.
+    pub fn template(mut self, key: SpecCategoryKey, template_id: SpecTemplateId) -> Self {
+        self.template_map.insert(key, template_id);
+        self
+    }
+
+    /// Add a spec index shared by every variant.
+    ///
+    /// The `spec_keys` of a variant are merged onto the base specs,
+    /// so a variant only needs to list the labels it adds or overrides.
+    pub fn base(mut self, key: SpecIndexKey) -> Self {
+        self.base_keys.push(key);
+        self
+    }
+
     pub fn build(self) -> ItemSpecsV1 {
         ItemSpecsV1 {
             map: self.map,
             index_map: self.index_map,
+            template_map: (!self.template_map.is_empty()).then_some(self.template_map),
+            base_keys: (!self.base_keys.is_empty()).then_some(self.base_keys),
         }
     }
 }
@@ -0,0 +1,473 @@
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+
+/// System of units a client prefers spec values in.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnitSystem {
+    Metric,
+    Imperial,
+}
+
+/// What a unit measures. Only units of the same dimension can be converted to each other.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnitDimension {
+    Length,
+    Mass,
+    Volume,
+    Temperature,
+    DataSize,
+    Power,
+    Frequency,
+    Duration,
+}
+
+/// Registry of the units spec values can be given in.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Millimeter,
+    Centimeter,
+    Meter,
+    Kilometer,
+    Inch,
+    Foot,
+    Yard,
+    Mile,
+    Gram,
+    Kilogram,
+    Ounce,
+    Pound,
+    Milliliter,
+    Liter,
+    FluidOunce,
+    Gallon,
+    Celsius,
+    Fahrenheit,
+    Byte,
+    Kilobyte,
+    Megabyte,
+    Gigabyte,
+    Terabyte,
+    Watt,
+    Kilowatt,
+    Hertz,
+    Megahertz,
+    Gigahertz,
+    Second,
+    Minute,
+    Hour,
+}
+
+impl Unit {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Unit::Millimeter => "mm",
+            Unit::Centimeter => "cm",
+            Unit::Meter => "m",
+            Unit::Kilometer => "km",
+            Unit::Inch => "in",
+            Unit::Foot => "ft",
+            Unit::Yard => "yd",
+            Unit::Mile => "mi",
+            Unit::Gram => "g",
+            Unit::Kilogram => "kg",
+            Unit::Ounce => "oz",
+            Unit::Pound => "lb",
+            Unit::Milliliter => "mL",
+            Unit::Liter => "L",
+            Unit::FluidOunce => "fl oz",
+            Unit::Gallon => "gal",
+            Unit::Celsius => "°C",
+            Unit::Fahrenheit => "°F",
+            Unit::Byte => "B",
+            Unit::Kilobyte => "KB",
+            Unit::Megabyte => "MB",
+            Unit::Gigabyte => "GB",
+            Unit::Terabyte => "TB",
+            Unit::Watt => "W",
+            Unit::Kilowatt => "kW",
+            Unit::Hertz => "Hz",
+            Unit::Megahertz => "MHz",
+            Unit::Gigahertz => "GHz",
+            Unit::Second => "s",
+            Unit::Minute => "min",
+            Unit::Hour => "h",
+        }
+    }
+
+    pub fn dimension(&self) -> UnitDimension {
+        match self {
+            Unit::Millimeter
+            | Unit::Centimeter
+            | Unit::Meter
+            | Unit::Kilometer
+            | Unit::Inch
+            | Unit::Foot
+            | Unit::Yard
+            | Unit::Mile => UnitDimension::Length,
+            Unit::Gram | Unit::Kilogram | Unit::Ounce | Unit::Pound => UnitDimension::Mass,
+            Unit::Milliliter | Unit::Liter | Unit::FluidOunce | Unit::Gallon => {
+                UnitDimension::Volume
+            }
+            Unit::Celsius | Unit::Fahrenheit => UnitDimension::Temperature,
+            Unit::Byte | Unit::Kilobyte | Unit::Megabyte | Unit::Gigabyte | Unit::Terabyte => {
+                UnitDimension::DataSize
+            }
+            Unit::Watt | Unit::Kilowatt => UnitDimension::Power,
+            Unit::Hertz | Unit::Megahertz | Unit::Gigahertz => UnitDimension::Frequency,
+            Unit::Second | Unit::Minute | Unit::Hour => UnitDimension::Duration,
+        }
+    }
+
+    /// The system the unit belongs to, or `None` for units used by both systems.
+    pub fn system(&self) -> Option<UnitSystem> {
+        match self {
+            Unit::Millimeter
+            | Unit::Centimeter
+            | Unit::Meter
+            | Unit::Kilometer
+            | Unit::Gram
+            | Unit::Kilogram
+            | Unit::Milliliter
+            | Unit::Liter
+            | Unit::Celsius => Some(UnitSystem::Metric),
+            Unit::Inch
+            | Unit::Foot
+            | Unit::Yard
+            | Unit::Mile
+            | Unit::Ounce
+            | Unit::Pound
+            | Unit::FluidOunce
+            | Unit::Gallon
+            | Unit::Fahrenheit => Some(UnitSystem::Imperial),
+            _ => None,
+        }
+    }
+
+    /// Factor and offset that convert a value in this unit to the base unit of its dimension:
+    /// meters, kilograms, liters, degrees Celsius, bytes, watts, hertz and seconds.
+    fn to_base(self) -> (f64, f64) {
+        match self {
+            Unit::Millimeter => (0.001, 0.0),
+            Unit::Centimeter => (0.01, 0.0),
+            Unit::Meter => (1.0, 0.0),
+            Unit::Kilometer => (1000.0, 0.0),
+            Unit::Inch => (0.0254, 0.0),
+            Unit::Foot => (0.3048, 0.0),
+            Unit::Yard => (0.9144, 0.0),
+            Unit::Mile => (1609.344, 0.0),
+            Unit::Gram => (0.001, 0.0),
+            Unit::Kilogram => (1.0, 0.0),
+            Unit::Ounce => (0.028_349_523_125, 0.0),
+            Unit::Pound => (0.453_592_37, 0.0),
+            Unit::Milliliter => (0.001, 0.0),
+            Unit::Liter => (1.0, 0.0),
+            Unit::FluidOunce => (0.029_573_529_562_5, 0.0),
+            Unit::Gallon => (3.785_411_784, 0.0),
+            Unit::Celsius => (1.0, 0.0),
+            Unit::Fahrenheit => (5.0 / 9.0, -32.0 * 5.0 / 9.0),
+            Unit::Byte => (1.0, 0.0),
+            Unit::Kilobyte => (1e3, 0.0),
+            Unit::Megabyte => (1e6, 0.0),
+            Unit::Gigabyte => (1e9, 0.0),
+            Unit::Terabyte => (1e12, 0.0),
+            Unit::Watt => (1.0, 0.0),
+            Unit::Kilowatt => (1e3, 0.0),
+            Unit::Hertz => (1.0, 0.0),
+            Unit::Megahertz => (1e6, 0.0),
+            Unit::Gigahertz => (1e9, 0.0),
+            Unit::Second => (1.0, 0.0),
+            Unit::Minute => (60.0, 0.0),
+            Unit::Hour => (3600.0, 0.0),
+        }
+    }
+
+    /// Converts a value in this unit to the base unit of its dimension.
+    pub fn base_value(&self, value: f64) -> f64 {
+        let (factor, offset) = self.to_base();
+        value * factor + offset
+    }
+
+    /// Converts a value between units of the same dimension.
+    pub fn convert(&self, value: f64, to: Unit) -> Option<f64> {
+        if self.dimension() != to.dimension() {
+            return None;
+        }
+        let (factor, offset) = to.to_base();
+        Some((self.base_value(value) - offset) / factor)
+    }
+
+    /// Returns the unit of `system` that a value in this unit is shown in.
+    /// Units already in the system, or used by both systems, are kept.
+    pub fn in_system(&self, system: UnitSystem) -> Unit {
+        match self.system() {
+            Some(own) if own != system => {}
+            _ => return *self,
+        }
+
+        match (system, self) {
+            (UnitSystem::Imperial, Unit::Millimeter | Unit::Centimeter) => Unit::Inch,
+            (UnitSystem::Imperial, Unit::Meter) => Unit::Foot,
+            (UnitSystem::Imperial, Unit::Kilometer) => Unit::Mile,
+            (UnitSystem::Imperial, Unit::Gram) => Unit::Ounce,
+            (UnitSystem::Imperial, Unit::Kilogram) => Unit::Pound,
+            (UnitSystem::Imperial, Unit::Milliliter) => Unit::FluidOunce,
+            (UnitSystem::Imperial, Unit::Liter) => Unit::Gallon,
+            (UnitSystem::Imperial, Unit::Celsius) => Unit::Fahrenheit,
+            (UnitSystem::Metric, Unit::Inch) => Unit::Centimeter,
+            (UnitSystem::Metric, Unit::Foot | Unit::Yard) => Unit::Meter,
+            (UnitSystem::Metric, Unit::Mile) => Unit::Kilometer,
+            (UnitSystem::Metric, Unit::Ounce) => Unit::Gram,
+            (UnitSystem::Metric, Unit::Pound) => Unit::Kilogram,
+            (UnitSystem::Metric, Unit::FluidOunce) => Unit::Milliliter,
+            (UnitSystem::Metric, Unit::Gallon) => Unit::Liter,
+            (UnitSystem::Metric, Unit::Fahrenheit) => Unit::Celsius,
+            _ => *self,
+        }
+    }
+}
+
+/// A spec value that can be compared and converted, unlike the strings of `SpecValue`.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub enum TypedSpecValue {
+    Number {
+        value: f64,
+        unit: Option<Unit>,
+    },
+    Range {
+        min: f64,
+        max: f64,
+        unit: Option<Unit>,
+    },
+    Boolean(bool),
+    // One of a fixed set of options, such as a panel type
+    Enum {
+        value: String,
+        options: Vec<String>,
+    },
+    Text(String),
+}
+
+/// Formats a number with at most two decimals and no trailing zeros.
+fn format_number(value: f64) -> String {
+    let formatted = format!("{:.2}", value);
+    formatted
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+fn format_with_unit(number: String, unit: Option<Unit>) -> String {
+    match unit {
+        Some(unit) => format!("{} {}", number, unit.symbol()),
+        None => number,
+    }
+}
+
+impl TypedSpecValue {
+    /// Converts the value to `system`. Values without a unit are returned unchanged.
+    pub fn in_system(&self, system: UnitSystem) -> Self {
+        let convert = |value: f64, unit: Unit| {
+            let to = unit.in_system(system);
+            (unit.convert(value, to).unwrap_or(value), to)
+        };
+
+        match self {
+            TypedSpecValue::Number {
+                value,
+                unit: Some(unit),
+            } => {
+                let (value, unit) = convert(*value, *unit);
+                TypedSpecValue::Number {
+                    value,
+                    unit: Some(unit),
+                }
+            }
+            TypedSpecValue::Range {
+                min,
+                max,
+                unit: Some(unit),
+            } => {
+                let (min, to) = convert(*min, *unit);
+                let (max, _) = convert(*max, *unit);
+                TypedSpecValue::Range {
+                    min,
+                    max,
+                    unit: Some(to),
+                }
+            }
+            other => other.clone(),
+        }
+    }
+
+    /// Returns the value in the base unit of its dimension, for comparing values
+    /// given in different units. Ranges are compared by their lower bound.
+    pub fn base_value(&self) -> Option<f64> {
+        match self {
+            TypedSpecValue::Number { value, unit }
+            | TypedSpecValue::Range {
+                min: value, unit, ..
+            } => Some(unit.map_or(*value, |unit| unit.base_value(*value))),
+            _ => None,
+        }
+    }
+
+    /// The unit of a number or range.
+    pub fn unit(&self) -> Option<Unit> {
+        match self {
+            TypedSpecValue::Number { unit, .. } | TypedSpecValue::Range { unit, .. } => *unit,
+            _ => None,
+        }
+    }
+
+    pub fn render(&self) -> String {
+        match self {
+            TypedSpecValue::Number { value, unit } => {
+                format_with_unit(format_number(*value), *unit)
+            }
+            TypedSpecValue::Range { min, max, unit } => format_with_unit(
+                format!("{}–{}", format_number(*min), format_number(*max)),
+                *unit,
+            ),
+            TypedSpecValue::Boolean(true) => "Yes".to_string(),
+            TypedSpecValue::Boolean(false) => "No".to_string(),
+            TypedSpecValue::Enum { value, .. } | TypedSpecValue::Text(value) => value.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL_UNITS: [Unit; 31] = [
+        Unit::Millimeter,
+        Unit::Centimeter,
+        Unit::Meter,
+        Unit::Kilometer,
+        Unit::Inch,
+        Unit::Foot,
+        Unit::Yard,
+        Unit::Mile,
+        Unit::Gram,
+        Unit::Kilogram,
+        Unit::Ounce,
+        Unit::Pound,
+        Unit::Milliliter,
+        Unit::Liter,
+        Unit::FluidOunce,
+        Unit::Gallon,
+        Unit::Celsius,
+        Unit::Fahrenheit,
+        Unit::Byte,
+        Unit::Kilobyte,
+        Unit::Megabyte,
+        Unit::Gigabyte,
+        Unit::Terabyte,
+        Unit::Watt,
+        Unit::Kilowatt,
+        Unit::Hertz,
+        Unit::Megahertz,
+        Unit::Gigahertz,
+        Unit::Second,
+        Unit::Minute,
+        Unit::Hour,
+    ];
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() <= 1e-9 * expected.abs().max(1.0),
+            "{} is not close to {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn conversions_round_trip_within_a_dimension() {
+        for from in ALL_UNITS {
+            for to in ALL_UNITS {
+                if from.dimension() != to.dimension() {
+                    continue;
+                }
+                for value in [-40.0, 0.0, 1.0, 12.5, 1234.0] {
+                    let converted = from.convert(value, to).unwrap();
+                    assert_close(to.convert(converted, from).unwrap(), value);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn conversions_across_dimensions_are_rejected() {
+        assert_eq!(Unit::Meter.convert(1.0, Unit::Kilogram), None);
+        assert_eq!(Unit::Celsius.convert(1.0, Unit::Second), None);
+    }
+
+    #[test]
+    fn linear_conversions_use_the_exact_factors() {
+        assert_close(Unit::Inch.convert(1.0, Unit::Centimeter).unwrap(), 2.54);
+        assert_close(Unit::Mile.convert(1.0, Unit::Kilometer).unwrap(), 1.609_344);
+        assert_close(Unit::Pound.convert(1.0, Unit::Ounce).unwrap(), 16.0);
+        assert_close(Unit::Hour.convert(1.0, Unit::Minute).unwrap(), 60.0);
+    }
+
+    #[test]
+    fn fahrenheit_conversion_applies_the_offset() {
+        assert_close(Unit::Fahrenheit.convert(32.0, Unit::Celsius).unwrap(), 0.0);
+        assert_close(
+            Unit::Fahrenheit.convert(212.0, Unit::Celsius).unwrap(),
+            100.0,
+        );
+        assert_close(Unit::Celsius.convert(37.0, Unit::Fahrenheit).unwrap(), 98.6);
+        assert_close(
+            Unit::Celsius.convert(-40.0, Unit::Fahrenheit).unwrap(),
+            -40.0,
+        );
+        assert_close(Unit::Fahrenheit.base_value(50.0), 10.0);
+    }
+
+    #[test]
+    fn values_convert_to_the_preferred_system() {
+        let value = TypedSpecValue::Range {
+            min: 0.0,
+            max: 100.0,
+            unit: Some(Unit::Celsius),
+        };
+
+        let TypedSpecValue::Range { min, max, unit } = value.in_system(UnitSystem::Imperial) else {
+            panic!("expected a range");
+        };
+        assert_close(min, 32.0);
+        assert_close(max, 212.0);
+        assert_eq!(unit, Some(Unit::Fahrenheit));
+    }
+
+    #[test]
+    fn units_of_both_systems_are_kept() {
+        assert_eq!(
+            Unit::Gigabyte.in_system(UnitSystem::Imperial),
+            Unit::Gigabyte
+        );
+        assert_eq!(Unit::Inch.in_system(UnitSystem::Imperial), Unit::Inch);
+    }
+
+    #[test]
+    fn rendering_rounds_to_two_decimals_without_trailing_zeros() {
+        let render =
+            |value: f64, unit: Option<Unit>| TypedSpecValue::Number { value, unit }.render();
+
+        assert_eq!(render(10.0, Some(Unit::Centimeter)), "10 cm");
+        assert_eq!(render(2.499, None), "2.5");
+        assert_eq!(render(3.14159, Some(Unit::Inch)), "3.14 in");
+        assert_eq!(render(0.0, None), "0");
+        assert_eq!(
+            TypedSpecValue::Number {
+                value: 100.0,
+                unit: Some(Unit::Centimeter),
+            }
+            .in_system(UnitSystem::Imperial)
+            .render(),
+            "39.37 in"
+        );
+    }
+}
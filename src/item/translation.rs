@@ -0,0 +1,67 @@
+use super::{attr::AttrIndexesResponseV2, ItemName};
+use crate::locale::{Locale, LocaleChain};
+use candid::{CandidType, Deserialize};
+use common::item::{attr::AttrType, spec::SpecResponse};
+use std::collections::BTreeMap;
+
+/// Translations of a text, by locale.
+pub type Translations = BTreeMap<Locale, String>;
+
+/// Translations of the content of an item. The content of the item itself is
+/// in the store default locale.
+///
+/// Attribute and spec names are translated by their original text,
+/// so a label used by several dimensions or categories is translated once.
+#[derive(CandidType, Deserialize, Debug, Clone, Default)]
+pub struct ItemTranslations {
+    pub name: BTreeMap<Locale, ItemName>,
+    pub descriptions: BTreeMap<Locale, Vec<String>>,
+    // Names of dimensions, such as `Color`
+    pub attr_names: BTreeMap<String, Translations>,
+    // Text labels and color names of attribute values, such as `Red`
+    pub attr_labels: BTreeMap<String, Translations>,
+    // Names of spec categories and labels
+    pub spec_names: BTreeMap<String, Translations>,
+}
+
+fn translate(glossary: &BTreeMap<String, Translations>, text: &mut String, chain: &LocaleChain) {
+    if let Some((_, translated)) = glossary
+        .get(text.as_str())
+        .and_then(|translations| chain.resolve(translations))
+    {
+        *text = translated.clone();
+    }
+}
+
+impl ItemTranslations {
+    pub fn name(&self, chain: &LocaleChain) -> Option<&ItemName> {
+        chain.resolve(&self.name).map(|(_, name)| name)
+    }
+
+    pub fn descriptions(&self, chain: &LocaleChain) -> Option<&Vec<String>> {
+        chain
+            .resolve(&self.descriptions)
+            .map(|(_, descriptions)| descriptions)
+    }
+
+    pub fn translate_attrs(&self, attrs: &mut AttrIndexesResponseV2, chain: &LocaleChain) {
+        for index in attrs.iter_mut().flatten() {
+            translate(&self.attr_names, &mut index.name, chain);
+            for value in index.value.iter_mut() {
+                match value {
+                    AttrType::Text(text) => translate(&self.attr_labels, text, chain),
+                    AttrType::Color(color) => translate(&self.attr_labels, &mut color.name, chain),
+                }
+            }
+        }
+    }
+
+    pub fn translate_specs(&self, specs: &mut SpecResponse, chain: &LocaleChain) {
+        for category in specs.iter_mut() {
+            translate(&self.spec_names, &mut category.category_name, chain);
+            for label in category.label_vec.iter_mut() {
+                translate(&self.spec_names, &mut label.label_name, chain);
+            }
+        }
+    }
+}
@@ -0,0 +1,422 @@
+use super::{
+    attr::{AttrKeysV2, AttrSpecificData},
+    get_item, modify_item, Item, ItemId, ItemUpdateErrorCode,
+};
+use crate::auth;
+use candid::{CandidType, Deserialize, Principal};
+use common::{item::attr::Stock, unit::Currency};
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+/// Which combinations of an item's dimensions are not sellable as they are.
+#[derive(CandidType, Deserialize, Debug, Clone, Default)]
+pub struct VariantCoverageReport {
+    pub combinations: u64,
+    pub defined: u64,
+    // Combinations without any data
+    pub missing: Vec<AttrKeysV2>,
+    // Combinations with data but no stock
+    pub out_of_stock: Vec<AttrKeysV2>,
+    // Combinations with data but no price in some of the checked currencies
+    pub missing_prices: Vec<MissingPrices>,
+    // Variants whose keys are not a combination of the current dimensions
+    pub orphaned: Vec<AttrKeysV2>,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct MissingPrices {
+    pub keys: AttrKeysV2,
+    pub currencies: Vec<Currency>,
+}
+
+/// Largest number of combinations `get_variant_coverage` checks in one query.
+pub const MAX_COVERAGE_COMBINATIONS: u64 = 10_000;
+
+/// Builds the coverage report of an item.
+///
+/// Prices are checked for `currencies`, or for every currency used by any variant when it is empty.
+/// Stock and prices are taken as sold, so a bundle is checked against its components.
+pub(crate) fn get_variant_coverage(
+    item_id: &ItemId,
+    currencies: Vec<Currency>,
+) -> Result<VariantCoverageReport, (ItemUpdateErrorCode, String)> {
+    let item = get_item(item_id)?;
+    match item.combination_count() {
+        Some(count) if count <= MAX_COVERAGE_COMBINATIONS => {}
+        _ => {
+            return Err((
+                ItemUpdateErrorCode::InvalidItem,
+                format!(
+                    "Item with id {} has more than {} combinations to check",
+                    item_id, MAX_COVERAGE_COMBINATIONS
+                ),
+            ))
+        }
+    }
+
+    let variants = item.variants();
+    let combinations = item.attr_key_combinations();
+
+    let currencies: BTreeSet<Currency> = if currencies.is_empty() {
+        variants
+            .iter()
+            .flat_map(|(_, data)| data.price.keys().cloned())
+            .collect()
+    } else {
+        currencies.into_iter().collect()
+    };
+
+    let mut report = VariantCoverageReport {
+        combinations: combinations.len() as u64,
+        defined: variants.len() as u64,
+        ..Default::default()
+    };
+
+    for keys in combinations.iter() {
+        let Some(data) = item.get_variant(keys) else {
+            report.missing.push(keys.clone());
+            continue;
+        };
+
+        if item.effective_stock(data) == 0 {
+            report.out_of_stock.push(keys.clone());
+        }
+
+        let missing_currencies: Vec<Currency> = currencies
+            .iter()
+            .filter(|currency| item.effective_price(data, currency).is_none())
+            .cloned()
+            .collect();
+        if !missing_currencies.is_empty() {
+            report.missing_prices.push(MissingPrices {
+                keys: keys.clone(),
+                currencies: missing_currencies,
+            });
+        }
+    }
+
+    let combinations: BTreeSet<AttrKeysV2> = combinations.into_iter().collect();
+    report.orphaned = variants
+        .into_iter()
+        .map(|(keys, _)| keys)
+        .filter(|keys| !combinations.contains(keys))
+        .collect();
+
+    Ok(report)
+}
+
+/// Largest number of combinations `generate_item_variants` fills in one call.
+pub const MAX_GENERATED_VARIANTS: u64 = 1000;
+
+impl Item {
+    /// Number of combinations of the item's dimension keys, `None` when it overflows.
+    pub fn combination_count(&self) -> Option<u64> {
+        self.attr_indexes()
+            .iter()
+            .flatten()
+            .try_fold(1u64, |count, index| {
+                count.checked_mul(index.map.len() as u64)
+            })
+    }
+}
+
+/// Fills the variant matrix of an item with copies of `template`.
+///
+/// Existing variants are kept unless `overwrite` is set.
+/// Returns the keys of the variants that were written.
+pub(crate) fn generate_item_variants(
+    author: Principal,
+    item_id: &ItemId,
+    template: AttrSpecificData,
+    overwrite: bool,
+) -> Result<Vec<AttrKeysV2>, (ItemUpdateErrorCode, String)> {
+    let author = auth::require_staff::<ItemUpdateErrorCode>(author)?;
+
+    modify_item(author, item_id, |item| {
+        match item.combination_count() {
+            Some(count) if count <= MAX_GENERATED_VARIANTS => {}
+            _ => {
+                return Err((
+                    ItemUpdateErrorCode::InvalidItem,
+                    format!(
+                        "Item with id {} has more than {} combinations to generate",
+                        item_id, MAX_GENERATED_VARIANTS
+                    ),
+                ))
+            }
+        }
+
+        let mut written = Vec::new();
+
+        for keys in item.attr_key_combinations() {
+            if !overwrite && item.get_variant(&keys).is_some() {
+                continue;
+            }
+            if item.insert_variant(&keys, template.clone()) {
+                written.push(keys);
+            }
+        }
+
+        Ok(written)
+    })
+}
+
+/// How a page request picks another variant when the requested one is unavailable.
+#[derive(CandidType, Deserialize, Debug, Clone, Default)]
+pub enum VariantFallbackStrategy {
+    /// The in-stock variant closest to the requested one.
+    #[default]
+    NearestInStock,
+    /// The cheapest in-stock variant in the requested currency.
+    Cheapest,
+    /// The given variant, or the one with default keys when `None`.
+    /// Falls back to `NearestInStock` when it is unavailable.
+    DefaultVariant(Option<AttrKeysV2>),
+}
+
+struct FallbackCandidate {
+    keys: AttrKeysV2,
+    is_preferred: bool,
+    keeps_changed_key: bool,
+    is_in_stock: bool,
+    distance: usize,
+    price: f64,
+}
+
+/// Orders the variants that have a price in `currency` from the best to the worst fallback.
+///
+/// Every strategy prefers, in this order, variants that keep the key of the dimension
+/// the user just changed, variants in stock, and variants that differ from the request
+/// in as few dimensions as possible. `DefaultVariant` puts its variant first among those
+/// that keep the changed key and are in stock, and `Cheapest` compares prices before distances.
+pub(crate) fn rank_fallback_candidates(
+    item: &Item,
+    requested: &AttrKeysV2,
+    changed_key_index: Option<usize>,
+    currency: &Currency,
+    strategy: &VariantFallbackStrategy,
+) -> Vec<AttrKeysV2> {
+    let dimensions = item.dimensions();
+    let requested = requested.normalized(dimensions);
+
+    let preferred = match strategy {
+        VariantFallbackStrategy::DefaultVariant(keys) => {
+            Some(keys.clone().unwrap_or_default().normalized(dimensions))
+        }
+        _ => None,
+    };
+
+    let candidates: Vec<FallbackCandidate> = item
+        .variants()
+        .into_iter()
+        .filter_map(|(keys, data)| {
+            let price = item.effective_price(data, currency)?.value();
+            let keys = keys.normalized(dimensions);
+
+            Some(FallbackCandidate {
+                is_preferred: preferred.as_ref() == Some(&keys),
+                keeps_changed_key: match changed_key_index {
+                    Some(i) => keys.get(i) == requested.get(i),
+                    None => true,
+                },
+                is_in_stock: item.effective_stock(data) > 0,
+                distance: (0..dimensions)
+                    .filter(|&i| keys.get(i) != requested.get(i))
+                    .count(),
+                price,
+                keys,
+            })
+        })
+        .collect();
+
+    rank_candidates(candidates, strategy)
+}
+
+fn rank_candidates(
+    mut candidates: Vec<FallbackCandidate>,
+    strategy: &VariantFallbackStrategy,
+) -> Vec<AttrKeysV2> {
+    candidates.sort_by(|a, b| {
+        b.keeps_changed_key
+            .cmp(&a.keeps_changed_key)
+            .then(b.is_in_stock.cmp(&a.is_in_stock))
+            .then(b.is_preferred.cmp(&a.is_preferred))
+            .then_with(|| match strategy {
+                VariantFallbackStrategy::Cheapest => a
+                    .price
+                    .total_cmp(&b.price)
+                    .then(a.distance.cmp(&b.distance)),
+                _ => a.distance.cmp(&b.distance),
+            })
+            .then_with(|| a.keys.cmp(&b.keys))
+    });
+
+    candidates.into_iter().map(|c| c.keys).collect()
+}
+
+/// Variants with this much stock or less are reported as `Low`.
+pub const LOW_STOCK_THRESHOLD: Stock = 5;
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub enum VariantStockState {
+    InStock,
+    Low,
+    OutOfStock,
+    UnavailableInCurrency,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct VariantAvailability {
+    pub keys: AttrKeysV2,
+    pub state: VariantStockState,
+}
+
+/// Every defined variant of an item with its stock state.
+/// Combinations that are not listed do not exist.
+pub type VariantAvailabilityMatrix = Vec<VariantAvailability>;
+
+impl Item {
+    pub fn get_availability_matrix(&self, currency: &Currency) -> VariantAvailabilityMatrix {
+        self.variants()
+            .into_iter()
+            .map(|(keys, data)| {
+                let stock = self.effective_stock(data);
+                let state = if self.effective_price(data, currency).is_none() {
+                    VariantStockState::UnavailableInCurrency
+                } else if stock == 0 {
+                    VariantStockState::OutOfStock
+                } else if stock <= LOW_STOCK_THRESHOLD {
+                    VariantStockState::Low
+                } else {
+                    VariantStockState::InStock
+                };
+
+                VariantAvailability {
+                    keys: keys.normalized(self.dimensions()),
+                    state,
+                }
+            })
+            .collect()
+    }
+}
+
+pub(crate) fn get_variant_availability(
+    item_id: &ItemId,
+    currency: &Currency,
+) -> Result<VariantAvailabilityMatrix, (ItemUpdateErrorCode, String)> {
+    Ok(get_item(item_id)?.get_availability_matrix(currency))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(keys: Vec<u8>, distance: usize, price: f64) -> FallbackCandidate {
+        FallbackCandidate {
+            keys: AttrKeysV2(keys.into_iter().map(Into::into).collect()),
+            is_preferred: false,
+            keeps_changed_key: true,
+            is_in_stock: true,
+            distance,
+            price,
+        }
+    }
+
+    fn keys(keys: Vec<u8>) -> AttrKeysV2 {
+        AttrKeysV2(keys.into_iter().map(Into::into).collect())
+    }
+
+    #[test]
+    fn nearest_in_stock_prefers_fewer_changed_dimensions() {
+        let candidates = vec![
+            candidate(vec![1, 1], 2, 10.0),
+            candidate(vec![0, 1], 1, 20.0),
+        ];
+
+        let ranked = rank_candidates(candidates, &VariantFallbackStrategy::NearestInStock);
+
+        assert_eq!(ranked, vec![keys(vec![0, 1]), keys(vec![1, 1])]);
+    }
+
+    #[test]
+    fn nearest_in_stock_prefers_stock_over_distance() {
+        let mut near = candidate(vec![0, 1], 1, 10.0);
+        near.is_in_stock = false;
+        let candidates = vec![near, candidate(vec![1, 1], 2, 10.0)];
+
+        let ranked = rank_candidates(candidates, &VariantFallbackStrategy::NearestInStock);
+
+        assert_eq!(ranked, vec![keys(vec![1, 1]), keys(vec![0, 1])]);
+    }
+
+    #[test]
+    fn changed_key_is_kept_before_stock() {
+        let mut drops_key = candidate(vec![0, 1], 1, 10.0);
+        drops_key.keeps_changed_key = false;
+        let mut keeps_key = candidate(vec![1, 0], 1, 10.0);
+        keeps_key.is_in_stock = false;
+        let candidates = vec![drops_key, keeps_key];
+
+        let ranked = rank_candidates(candidates, &VariantFallbackStrategy::NearestInStock);
+
+        assert_eq!(ranked, vec![keys(vec![1, 0]), keys(vec![0, 1])]);
+    }
+
+    #[test]
+    fn cheapest_compares_prices_before_distances() {
+        let candidates = vec![
+            candidate(vec![0, 1], 1, 20.0),
+            candidate(vec![1, 1], 2, 10.0),
+        ];
+
+        let ranked = rank_candidates(candidates, &VariantFallbackStrategy::Cheapest);
+
+        assert_eq!(ranked, vec![keys(vec![1, 1]), keys(vec![0, 1])]);
+    }
+
+    #[test]
+    fn cheapest_prefers_stock_over_price() {
+        let mut cheap = candidate(vec![1, 1], 2, 10.0);
+        cheap.is_in_stock = false;
+        let candidates = vec![cheap, candidate(vec![0, 1], 1, 20.0)];
+
+        let ranked = rank_candidates(candidates, &VariantFallbackStrategy::Cheapest);
+
+        assert_eq!(ranked, vec![keys(vec![0, 1]), keys(vec![1, 1])]);
+    }
+
+    #[test]
+    fn default_variant_comes_first_when_in_stock() {
+        let mut default = candidate(vec![0, 0], 2, 10.0);
+        default.is_preferred = true;
+        let candidates = vec![candidate(vec![1, 0], 1, 10.0), default];
+
+        let ranked = rank_candidates(candidates, &VariantFallbackStrategy::DefaultVariant(None));
+
+        assert_eq!(ranked, vec![keys(vec![0, 0]), keys(vec![1, 0])]);
+    }
+
+    #[test]
+    fn default_variant_out_of_stock_falls_back_to_nearest_in_stock() {
+        let mut default = candidate(vec![0, 0], 1, 10.0);
+        default.is_preferred = true;
+        default.is_in_stock = false;
+        let candidates = vec![default, candidate(vec![1, 1], 2, 10.0)];
+
+        let ranked = rank_candidates(candidates, &VariantFallbackStrategy::DefaultVariant(None));
+
+        assert_eq!(ranked, vec![keys(vec![1, 1]), keys(vec![0, 0])]);
+    }
+
+    #[test]
+    fn default_variant_does_not_drop_the_changed_key() {
+        let mut default = candidate(vec![0, 0], 1, 10.0);
+        default.is_preferred = true;
+        default.keeps_changed_key = false;
+        let candidates = vec![default, candidate(vec![1, 1], 2, 10.0)];
+
+        let ranked = rank_candidates(candidates, &VariantFallbackStrategy::DefaultVariant(None));
+
+        assert_eq!(ranked, vec![keys(vec![1, 1]), keys(vec![0, 0])]);
+    }
+}
@@ -2,27 +2,71 @@
 extern crate nestify;
 
 use candid::Principal;
-use ic_cdk_macros::*;
-use ic_stable_structures::{
-    memory_manager::{MemoryId, MemoryManager, VirtualMemory},
-    DefaultMemoryImpl, StableBTreeMap, StableCell, StableLog,
-};
 use common::{
     item::{
-        ItemId, ItemKey, ItemPageFromStoreErrorCode, ItemPageRequestToStoreCanister,
+        attr::Stock, ItemId, ItemKey, ItemPageFromStoreErrorCode, ItemPageRequestToStoreCanister,
         ItemPageResponseFromStoreCanister,
     },
     store::{StoreId, StoreInitArg, StoreName},
+    unit::Currency,
 };
+use ic_cdk_macros::*;
+use ic_stable_structures::{
+    memory_manager::{MemoryId, MemoryManager, VirtualMemory},
+    DefaultMemoryImpl, StableBTreeMap, StableCell, StableLog,
+};
+use serde_bytes::ByteBuf;
 use std::cell::RefCell;
 
+pub mod asset;
+mod auth;
+pub mod backup;
+pub mod currency;
 pub mod data;
+pub mod feed;
+pub mod http;
 pub mod item;
+pub mod locale;
 mod log;
+pub mod media;
+pub mod spec_template;
+pub mod wishlist;
 
+use asset::{Asset, AssetChunkKey, AssetErrorCode, AssetId};
+use auth::AuthErrorCode;
+use backup::{BackupChunk, BackupCursor, BackupErrorCode, BackupRequest, RestoreState};
 use data::StoreData;
-use item::Item;
+use feed::{ProductFeedPage, ProductFeedRequest, ProductFeedSettings};
+use http::{HttpRequest, HttpResponse, StreamingCallbackHttpResponse, StreamingCallbackToken};
+use item::{
+    attr::{AttrKeysV2, AttrSpecificData},
+    bundle::{BundleErrorCode, ItemBundle},
+    compare::{CompareErrorCode, CompareItemsRequest, ComparisonTable},
+    filter::{SpecFilterRequest, SpecFilterResponse, SpecValueIndexEntry, SpecValueIndexKey},
+    image::{GalleryOperation, ImageKey, ImageVecKey},
+    import::{ImportErrorCode, ImportReport, ImportRequest},
+    question::{
+        AnswerIndex, AnswerInput, QaErrorCode, QaModeration, QaVoteKey, Question, QuestionId,
+        QuestionKey, QuestionPage, QuestionQuery,
+    },
+    relation::{ItemRelation, ItemRelationKey, ItemRelations, RelationErrorCode},
+    review::{
+        RatingSummary, Review, ReviewErrorCode, ReviewInput, ReviewKey, ReviewModeration,
+        ReviewPage, ReviewQuery,
+    },
+    revision::{
+        ItemRevision, ItemRevisionDiff, ItemRevisionKey, ItemRevisionSummary, RevisionNumber,
+        RevisionSelector,
+    },
+    spec::SpecCategory,
+    variant::{VariantAvailabilityMatrix, VariantCoverageReport},
+    Item, ItemPageRequestV2, ItemPageResponseV2, ItemPatch, ItemUpdateErrorCode,
+};
+use locale::Locale;
 use log::{LogEntry, LogLevel};
+use media::{MediaAsset, MediaErrorCode, MediaId, RenditionSettings};
+use spec_template::{SpecTemplateErrorCode, SpecTemplateId};
+use wishlist::{Wishlist, WishlistEntryInput, WishlistEntryStatus, WishlistErrorCode, WishlistKey};
 
 type Memory = VirtualMemory<DefaultMemoryImpl>;
 
@@ -55,6 +99,107 @@ thread_local! {
             MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(4))),
         )
     );
+
+    pub(crate) static ITEM_REVISIONS: RefCell<StableBTreeMap<ItemRevisionKey, ItemRevision, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(5))),
+        )
+    );
+
+    pub(crate) static MEDIA_LIBRARY: RefCell<StableBTreeMap<MediaId, MediaAsset, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(6))),
+        )
+    );
+
+    pub(crate) static STAFF: RefCell<StableBTreeMap<Principal, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(7))),
+        )
+    );
+
+    pub(crate) static ASSETS: RefCell<StableBTreeMap<AssetId, Asset, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(8))),
+        )
+    );
+
+    pub(crate) static ASSET_CHUNKS: RefCell<StableBTreeMap<AssetChunkKey, Vec<u8>, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(9))),
+        )
+    );
+
+    pub(crate) static SPEC_TEMPLATES: RefCell<StableBTreeMap<SpecTemplateId, SpecCategory, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(10))),
+        )
+    );
+
+    pub(crate) static SPEC_VALUE_INDEX: RefCell<StableBTreeMap<SpecValueIndexKey, SpecValueIndexEntry, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(11))),
+        )
+    );
+
+    pub(crate) static ITEM_RELATIONS: RefCell<StableBTreeMap<ItemRelationKey, ItemRelations, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(12))),
+        )
+    );
+
+    pub(crate) static REVIEWS: RefCell<StableBTreeMap<ReviewKey, Review, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(13))),
+        )
+    );
+
+    pub(crate) static ITEM_RATINGS: RefCell<StableBTreeMap<ItemId, RatingSummary, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(14))),
+        )
+    );
+
+    pub(crate) static QUESTIONS: RefCell<StableBTreeMap<QuestionKey, Question, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(15))),
+        )
+    );
+
+    pub(crate) static QA_VOTES: RefCell<StableBTreeMap<QaVoteKey, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(16))),
+        )
+    );
+
+    pub(crate) static WISHLISTS: RefCell<StableBTreeMap<WishlistKey, Wishlist, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(17))),
+        )
+    );
+
+    pub(crate) static RESTORE_STATE: RefCell<StableCell<RestoreState, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(18))),
+            RestoreState::default(),
+        ).unwrap()
+    );
+
+    // Next id handed out by `add_media`, never lowered so removed ids are not reused
+    pub(crate) static NEXT_MEDIA_ID: RefCell<StableCell<MediaId, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(19))),
+            0,
+        ).unwrap()
+    );
+
+    // Next id handed out by `begin_asset_upload`, never lowered so deleted ids are not reused
+    pub(crate) static NEXT_ASSET_ID: RefCell<StableCell<AssetId, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(20))),
+            0,
+        ).unwrap()
+    );
 }
 
 #[init]
@@ -64,6 +209,11 @@ fn init_store(arg: Option<StoreInitArg>) {
     }
 }
 
+#[post_upgrade]
+fn post_upgrade_store() {
+    asset::rebuild_certified_assets();
+}
+
 #[update]
 fn update_store_data(caller: Principal, id: StoreId, name: StoreName) {
     let _ = data::update_store_data(id, name);
@@ -76,6 +226,32 @@ fn get_item_page_data_from_store(
 ) -> Result<ItemPageResponseFromStoreCanister, (ItemPageFromStoreErrorCode, String)> {
     let res = crate::item::get_item_page_data(&arg);
 
+    log_item_page_result(caller, &res);
+
+    res
+}
+
+#[query]
+fn compare_items(arg: CompareItemsRequest) -> Result<ComparisonTable, (CompareErrorCode, String)> {
+    crate::item::compare::compare_items(&arg)
+}
+
+#[query]
+fn get_item_page_data_v2_from_store(
+    caller: Principal,
+    arg: ItemPageRequestV2,
+) -> Result<ItemPageResponseV2, (ItemPageFromStoreErrorCode, String)> {
+    let res = crate::item::get_item_page_data_v2(&arg);
+
+    log_item_page_result(caller, &res);
+
+    res
+}
+
+fn log_item_page_result<T>(
+    caller: Principal,
+    res: &Result<T, (ItemPageFromStoreErrorCode, String)>,
+) {
     let log_entry = match res.as_ref() {
         Ok(_) => LogEntry::new(LogLevel::Info, Some(caller), "get_item_page_data: Ok", None),
         Err((code, message)) => {
@@ -94,11 +270,475 @@ fn get_item_page_data_from_store(
     };
 
     LOG.with(|log| log.borrow_mut().append(&log_entry).unwrap());
+}
 
-    res
+#[update]
+async fn insert_items_to_store(
+    caller: Principal,
+    vec: Vec<Item>,
+) -> Result<Vec<(ItemId, ItemKey)>, (ItemUpdateErrorCode, String)> {
+    let author = auth::require_staff::<ItemUpdateErrorCode>(caller)?;
+    Ok(crate::item::insert_items(author, vec).await)
+}
+
+#[update]
+async fn import_items_to_store(
+    caller: Principal,
+    arg: ImportRequest,
+) -> Result<ImportReport, (ImportErrorCode, String)> {
+    crate::item::import::import_items(caller, arg).await
+}
+
+#[update]
+fn patch_item_in_store(
+    caller: Principal,
+    item_id: ItemId,
+    patch: ItemPatch,
+) -> Result<(), (ItemUpdateErrorCode, String)> {
+    crate::item::patch_item(caller, &item_id, patch)
+}
+
+#[update]
+fn update_item_gallery(
+    caller: Principal,
+    item_id: ItemId,
+    key: ImageVecKey,
+    op: GalleryOperation,
+) -> Result<Vec<ImageKey>, (ItemUpdateErrorCode, String)> {
+    crate::item::update_item_gallery(caller, &item_id, key, op)
+}
+
+#[update]
+fn migrate_item_to_v2(
+    caller: Principal,
+    item_id: ItemId,
+) -> Result<(), (ItemUpdateErrorCode, String)> {
+    crate::item::migrate_item_to_v2(caller, &item_id)
+}
+
+#[query]
+fn list_item_revisions(item_id: ItemId) -> Vec<ItemRevisionSummary> {
+    crate::item::revision::list_item_revisions(&item_id)
+}
+
+#[query]
+fn get_item_revision(item_id: ItemId, revision: RevisionNumber) -> Option<ItemRevision> {
+    crate::item::revision::get_item_revision(&item_id, revision)
+}
+
+#[query]
+fn diff_item_revisions(
+    item_id: ItemId,
+    from: RevisionSelector,
+    to: RevisionSelector,
+) -> Result<ItemRevisionDiff, (ItemUpdateErrorCode, String)> {
+    crate::item::revision::diff_item_revisions(&item_id, from, to)
+}
+
+#[update]
+fn rollback_item(
+    caller: Principal,
+    item_id: ItemId,
+    revision: RevisionNumber,
+) -> Result<RevisionNumber, (ItemUpdateErrorCode, String)> {
+    crate::item::revision::rollback_item(caller, &item_id, revision)
+}
+
+#[update]
+fn generate_item_variants(
+    caller: Principal,
+    item_id: ItemId,
+    template: AttrSpecificData,
+    overwrite: bool,
+) -> Result<Vec<AttrKeysV2>, (ItemUpdateErrorCode, String)> {
+    crate::item::variant::generate_item_variants(caller, &item_id, template, overwrite)
+}
+
+#[query]
+fn get_item_variant_coverage(
+    item_id: ItemId,
+    currencies: Vec<Currency>,
+) -> Result<VariantCoverageReport, (ItemUpdateErrorCode, String)> {
+    crate::item::variant::get_variant_coverage(&item_id, currencies)
+}
+
+#[query]
+fn get_item_variant_availability(
+    item_id: ItemId,
+    currency: Currency,
+) -> Result<VariantAvailabilityMatrix, (ItemUpdateErrorCode, String)> {
+    crate::item::variant::get_variant_availability(&item_id, &currency)
+}
+
+#[update]
+fn add_media_to_store(
+    caller: Principal,
+    vec: Vec<MediaAsset>,
+) -> Result<Vec<MediaId>, (MediaErrorCode, String)> {
+    crate::media::add_media(caller, vec)
+}
+
+#[update]
+fn update_media_in_store(
+    caller: Principal,
+    id: MediaId,
+    asset: MediaAsset,
+) -> Result<(), (MediaErrorCode, String)> {
+    crate::media::update_media(caller, id, asset)
+}
+
+#[update]
+fn remove_media_from_store(
+    caller: Principal,
+    id: MediaId,
+) -> Result<MediaAsset, (MediaErrorCode, String)> {
+    crate::media::remove_media(caller, id)
+}
+
+#[query]
+fn get_media(id: MediaId) -> Option<MediaAsset> {
+    crate::media::get_media(id)
+}
+
+#[query]
+fn list_media(start: MediaId, limit: u32) -> Vec<(MediaId, MediaAsset)> {
+    crate::media::list_media(start, limit)
+}
+
+#[update]
+fn add_spec_template(
+    caller: Principal,
+    template: SpecCategory,
+) -> Result<SpecTemplateId, (SpecTemplateErrorCode, String)> {
+    crate::spec_template::add_spec_template(caller, template)
+}
+
+#[update]
+fn update_spec_template(
+    caller: Principal,
+    id: SpecTemplateId,
+    template: SpecCategory,
+) -> Result<(), (SpecTemplateErrorCode, String)> {
+    crate::spec_template::update_spec_template(caller, id, template)
 }
 
 #[update]
-async fn insert_items_to_store(caller: Principal, vec: Vec<Item>) -> Vec<(ItemId, ItemKey)> {
-    crate::item::insert_items(vec).await
+fn remove_spec_template(
+    caller: Principal,
+    id: SpecTemplateId,
+) -> Result<SpecCategory, (SpecTemplateErrorCode, String)> {
+    crate::spec_template::remove_spec_template(caller, id)
+}
+
+#[query]
+fn get_spec_template(id: SpecTemplateId) -> Option<SpecCategory> {
+    crate::spec_template::get_spec_template(id)
+}
+
+#[query]
+fn list_spec_templates(start: SpecTemplateId, limit: u32) -> Vec<(SpecTemplateId, SpecCategory)> {
+    crate::spec_template::list_spec_templates(start, limit)
+}
+
+#[update]
+fn add_staff_to_store(caller: Principal, staff: Principal) -> Result<(), (AuthErrorCode, String)> {
+    crate::auth::add_staff(caller, staff)
+}
+
+#[update]
+fn remove_staff_from_store(
+    caller: Principal,
+    staff: Principal,
+) -> Result<(), (AuthErrorCode, String)> {
+    crate::auth::remove_staff(caller, staff)
+}
+
+#[query]
+fn list_staff() -> Vec<Principal> {
+    crate::auth::list_staff()
+}
+
+#[update]
+fn begin_asset_upload(
+    caller: Principal,
+    content_type: String,
+) -> Result<AssetId, (AssetErrorCode, String)> {
+    crate::asset::begin_asset_upload(caller, content_type)
+}
+
+#[update]
+fn append_asset_chunk(
+    caller: Principal,
+    asset_id: AssetId,
+    index: u32,
+    bytes: ByteBuf,
+) -> Result<(), (AssetErrorCode, String)> {
+    crate::asset::append_asset_chunk(caller, asset_id, index, bytes)
+}
+
+#[update]
+fn commit_asset_upload(
+    caller: Principal,
+    asset_id: AssetId,
+    sha256: ByteBuf,
+) -> Result<String, (AssetErrorCode, String)> {
+    crate::asset::commit_asset_upload(caller, asset_id, sha256)
+}
+
+#[update]
+fn delete_asset(caller: Principal, asset_id: AssetId) -> Result<(), (AssetErrorCode, String)> {
+    crate::asset::delete_asset(caller, asset_id)
+}
+
+#[query]
+fn get_asset_info(asset_id: AssetId) -> Option<Asset> {
+    crate::asset::get_asset_info(asset_id)
+}
+
+#[query]
+fn http_request(req: HttpRequest) -> HttpResponse {
+    crate::http::http_request(req)
+}
+
+#[query]
+fn http_request_streaming_callback(token: StreamingCallbackToken) -> StreamingCallbackHttpResponse {
+    crate::asset::http_request_streaming_callback(token)
+}
+
+#[update]
+fn update_rendition_settings(
+    caller: Principal,
+    settings: Option<RenditionSettings>,
+) -> Result<(), (AuthErrorCode, String)> {
+    crate::media::update_rendition_settings(caller, settings)
+}
+
+#[query]
+fn export_product_feed(arg: ProductFeedRequest) -> ProductFeedPage {
+    crate::feed::export_product_feed(&arg)
+}
+
+#[update]
+fn update_default_locale(
+    caller: Principal,
+    locale: Option<Locale>,
+) -> Result<(), (AuthErrorCode, String)> {
+    crate::locale::update_default_locale(caller, locale)
+}
+
+#[update]
+fn update_product_feed_settings(
+    caller: Principal,
+    settings: Option<ProductFeedSettings>,
+) -> Result<(), (AuthErrorCode, String)> {
+    crate::feed::update_product_feed_settings(caller, settings)
+}
+
+#[query]
+fn export_backup(
+    caller: Principal,
+    arg: BackupRequest,
+) -> Result<BackupChunk, (BackupErrorCode, String)> {
+    crate::backup::export_backup(caller, arg)
+}
+
+#[update]
+fn begin_restore(caller: Principal) -> Result<(), (BackupErrorCode, String)> {
+    crate::backup::begin_restore(caller)
+}
+
+#[update]
+fn restore_backup_chunk(
+    caller: Principal,
+    chunk: BackupChunk,
+) -> Result<Option<BackupCursor>, (BackupErrorCode, String)> {
+    crate::backup::restore_backup_chunk(caller, chunk)
+}
+
+#[query]
+fn filter_items_by_specs(arg: SpecFilterRequest) -> SpecFilterResponse {
+    crate::item::filter::filter_items(&arg)
+}
+
+#[update]
+fn rebuild_spec_index(caller: Principal) -> Result<u64, (AuthErrorCode, String)> {
+    crate::item::filter::rebuild_spec_index(caller)
+}
+
+#[update]
+fn set_item_relations(
+    caller: Principal,
+    key: ItemRelationKey,
+    relations: Vec<ItemRelation>,
+) -> Result<(), (RelationErrorCode, String)> {
+    crate::item::relation::set_item_relations(caller, key, relations)
+}
+
+#[query]
+fn list_item_relations(item_id: ItemId) -> Vec<(Option<AttrKeysV2>, Vec<ItemRelation>)> {
+    crate::item::relation::list_item_relations(item_id)
+}
+
+#[update]
+fn prune_item_relations(caller: Principal) -> Result<u64, (RelationErrorCode, String)> {
+    crate::item::relation::prune_item_relations(caller)
+}
+
+#[update]
+fn set_item_bundle(
+    caller: Principal,
+    item_id: ItemId,
+    bundle: Option<ItemBundle>,
+) -> Result<(), (BundleErrorCode, String)> {
+    crate::item::bundle::set_item_bundle(caller, &item_id, bundle)
+}
+
+#[update]
+fn sell_bundle(
+    caller: Principal,
+    item_id: ItemId,
+    count: Stock,
+) -> Result<Stock, (BundleErrorCode, String)> {
+    crate::item::bundle::sell_bundle(caller, &item_id, count)
+}
+
+#[update]
+fn post_review(
+    caller: Principal,
+    item_id: ItemId,
+    input: ReviewInput,
+) -> Result<(), (ReviewErrorCode, String)> {
+    crate::item::review::post_review(caller, item_id, input)
+}
+
+#[update]
+fn delete_review(caller: Principal, item_id: ItemId) -> Result<(), (ReviewErrorCode, String)> {
+    crate::item::review::delete_review(caller, item_id)
+}
+
+#[update]
+fn moderate_review(
+    caller: Principal,
+    key: ReviewKey,
+    action: ReviewModeration,
+) -> Result<(), (ReviewErrorCode, String)> {
+    crate::item::review::moderate_review(caller, key, action)
+}
+
+#[query]
+fn list_item_reviews(item_id: ItemId, query: ReviewQuery) -> ReviewPage {
+    crate::item::review::list_item_reviews(item_id, query)
+}
+
+#[query]
+fn get_own_review(
+    caller: Principal,
+    item_id: ItemId,
+) -> Result<Option<Review>, (ReviewErrorCode, String)> {
+    crate::item::review::get_own_review(caller, item_id)
+}
+
+#[query]
+fn list_pending_reviews(
+    caller: Principal,
+    offset: u64,
+    limit: u64,
+) -> Result<Vec<(ReviewKey, Review)>, (ReviewErrorCode, String)> {
+    crate::item::review::list_pending_reviews(caller, offset, limit)
+}
+
+#[update]
+fn ask_question(
+    caller: Principal,
+    item_id: ItemId,
+    text: String,
+) -> Result<QuestionId, (QaErrorCode, String)> {
+    crate::item::question::ask_question(caller, item_id, text)
+}
+
+#[update]
+fn answer_question(
+    caller: Principal,
+    key: QuestionKey,
+    input: AnswerInput,
+) -> Result<AnswerIndex, (QaErrorCode, String)> {
+    crate::item::question::answer_question(caller, key, input)
+}
+
+#[update]
+fn moderate_question(
+    caller: Principal,
+    key: QuestionKey,
+    answer: Option<AnswerIndex>,
+    action: QaModeration,
+) -> Result<(), (QaErrorCode, String)> {
+    crate::item::question::moderate_question(caller, key, answer, action)
+}
+
+#[update]
+fn upvote_question(
+    caller: Principal,
+    key: QuestionKey,
+    answer: Option<AnswerIndex>,
+) -> Result<u64, (QaErrorCode, String)> {
+    crate::item::question::upvote(caller, key, answer)
+}
+
+#[query]
+fn list_item_questions(item_id: ItemId, query: QuestionQuery) -> QuestionPage {
+    crate::item::question::list_item_questions(item_id, query)
+}
+
+#[query]
+fn list_pending_questions(
+    caller: Principal,
+    offset: u64,
+    limit: u64,
+) -> Result<Vec<(QuestionKey, Question)>, (QaErrorCode, String)> {
+    crate::item::question::list_pending_questions(caller, offset, limit)
+}
+
+#[update]
+fn update_verified_buyer_answers(
+    caller: Principal,
+    enabled: bool,
+) -> Result<(), (AuthErrorCode, String)> {
+    crate::item::question::update_verified_buyer_answers(caller, enabled)
+}
+
+#[update]
+fn add_to_wishlist(
+    caller: Principal,
+    name: String,
+    input: WishlistEntryInput,
+) -> Result<(), (WishlistErrorCode, String)> {
+    crate::wishlist::add_to_wishlist(caller, name, input)
+}
+
+#[update]
+fn remove_from_wishlist(
+    caller: Principal,
+    name: String,
+    item_id: ItemId,
+    attr_keys: Option<AttrKeysV2>,
+) -> Result<(), (WishlistErrorCode, String)> {
+    crate::wishlist::remove_from_wishlist(caller, name, item_id, attr_keys)
+}
+
+#[update]
+fn delete_wishlist(caller: Principal, name: String) -> Result<(), (WishlistErrorCode, String)> {
+    crate::wishlist::delete_wishlist(caller, name)
+}
+
+#[query]
+fn list_wishlists(caller: Principal) -> Result<Vec<(String, u64)>, (WishlistErrorCode, String)> {
+    crate::wishlist::list_wishlists(caller)
+}
+
+#[query]
+fn get_wishlist(
+    caller: Principal,
+    name: String,
+) -> Result<Vec<WishlistEntryStatus>, (WishlistErrorCode, String)> {
+    crate::wishlist::get_wishlist(caller, name)
 }
@@ -0,0 +1,94 @@
+use crate::{
+    auth::{self, AuthErrorCode},
+    data,
+};
+use candid::Principal;
+use std::collections::BTreeMap;
+
+/// A BCP 47 language tag, e.g. `ja-JP` or `en`.
+pub type Locale = String;
+
+/// Resolves translated content through a list of locales, most preferred first.
+///
+/// Each preferred locale is followed by its parents, so `ja-JP` is followed by `ja`.
+/// The chain ends at the store default locale, whose content is the untranslated original.
+#[derive(Debug, Clone, Default)]
+pub struct LocaleChain {
+    locales: Vec<Locale>,
+    default_locale: Option<Locale>,
+}
+
+impl LocaleChain {
+    pub fn new(preferred: &[Locale], default_locale: Option<Locale>) -> Self {
+        let mut locales: Vec<Locale> = Vec::new();
+
+        for locale in preferred {
+            let mut tag = locale.trim();
+            loop {
+                if !tag.is_empty() && !locales.iter().any(|l| l.eq_ignore_ascii_case(tag)) {
+                    locales.push(tag.to_string());
+                }
+                match tag.rsplit_once('-') {
+                    Some((parent, _)) => tag = parent,
+                    None => break,
+                }
+            }
+        }
+
+        Self {
+            locales,
+            default_locale,
+        }
+    }
+
+    /// Builds the chain for the preferred locales of a request,
+    /// ending at the default locale of the store.
+    pub fn for_store(preferred: &[Locale]) -> Self {
+        let default_locale = data::get_store_data().and_then(|data| data.default_locale);
+        Self::new(preferred, default_locale)
+    }
+
+    fn is_default(&self, locale: &str) -> bool {
+        self.default_locale
+            .as_ref()
+            .is_some_and(|default| default.eq_ignore_ascii_case(locale))
+    }
+
+    /// Returns the first translation along the chain with the locale it was found in.
+    /// Returns `None` when the chain reaches the store default locale first,
+    /// or when no locale of the chain is translated, meaning the original should be used.
+    pub fn resolve<'a, T>(
+        &self,
+        translations: &'a BTreeMap<Locale, T>,
+    ) -> Option<(&'a Locale, &'a T)> {
+        for locale in &self.locales {
+            if self.is_default(locale) {
+                return None;
+            }
+            let found = translations
+                .iter()
+                .find(|(l, _)| l.eq_ignore_ascii_case(locale));
+            if found.is_some() {
+                return found;
+            }
+        }
+        None
+    }
+
+    /// Returns the locale content resolved through `resolve` is in.
+    pub fn resolved_locale<T>(&self, translations: &BTreeMap<Locale, T>) -> Option<Locale> {
+        self.resolve(translations)
+            .map(|(locale, _)| locale.clone())
+            .or_else(|| self.default_locale.clone())
+    }
+}
+
+pub(crate) fn update_default_locale(
+    caller: Principal,
+    locale: Option<Locale>,
+) -> Result<(), (AuthErrorCode, String)> {
+    auth::require_owner::<AuthErrorCode>(caller)?;
+    let _ = data::update_default_locale(locale);
+
+    Ok(())
+}
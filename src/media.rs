@@ -0,0 +1,330 @@
+use crate::{
+    asset,
+    auth::{self, AuthErrorCode},
+    data,
+    item::{image::MediaKind, review::ReviewKey, revision::ItemRevisionKey, ItemId},
+    ITEMS, ITEMS_IN_ID, ITEM_REVISIONS, MEDIA_LIBRARY, NEXT_MEDIA_ID, REVIEWS,
+};
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use common::item::MediaDataWithCaption;
+use ic_stable_structures::{storable::Bound, Storable};
+use serde::Serialize;
+use std::borrow::Cow;
+
+/// Key of a media entry in the store-level library.
+pub type MediaId = u64;
+
+/// A media entry shared by every item that references it.
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct MediaAsset {
+    pub media: MediaDataWithCaption,
+    pub metadata: MediaMetadata,
+    // `None` for plain images
+    pub kind: Option<MediaKind>,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, Default)]
+pub struct MediaMetadata {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub mime_type: Option<String>,
+    pub alt_text: Option<String>,
+}
+
+impl Storable for MediaAsset {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum MediaErrorCode {
+    Unauthorized,
+    MediaNotFound,
+    MediaInUse,
+}
+
+impl From<AuthErrorCode> for MediaErrorCode {
+    fn from(code: AuthErrorCode) -> Self {
+        match code {
+            AuthErrorCode::Unauthorized => MediaErrorCode::Unauthorized,
+        }
+    }
+}
+
+/// Adds entries to the library under fresh ids. Ids of removed entries are never handed out
+/// again, so a stale reference can't resolve to unrelated media.
+///
+/// Only staff can add media, including the media attached to reviews.
+pub(crate) fn add_media(
+    caller: Principal,
+    vec: Vec<MediaAsset>,
+) -> Result<Vec<MediaId>, (MediaErrorCode, String)> {
+    auth::require_staff::<MediaErrorCode>(caller)?;
+
+    let ids: Vec<MediaId> = MEDIA_LIBRARY.with_borrow_mut(|p| {
+        // Entries restored from a backup may lie beyond the counter
+        let mut next_id = NEXT_MEDIA_ID
+            .with_borrow(|c| *c.get())
+            .max(p.last_key_value().map_or(0, |(id, _)| id + 1));
+
+        vec.into_iter()
+            .map(|asset| {
+                let id = next_id;
+                p.insert(id, asset);
+                next_id += 1;
+                id
+            })
+            .collect()
+    });
+
+    if let Some(last) = ids.last() {
+        let _ = NEXT_MEDIA_ID.with_borrow_mut(|c| c.set(last + 1));
+    }
+
+    Ok(ids)
+}
+
+pub(crate) fn update_media(
+    caller: Principal,
+    id: MediaId,
+    asset: MediaAsset,
+) -> Result<(), (MediaErrorCode, String)> {
+    auth::require_staff::<MediaErrorCode>(caller)?;
+
+    MEDIA_LIBRARY.with_borrow_mut(|p| {
+        if !p.contains_key(&id) {
+            return Err((
+                MediaErrorCode::MediaNotFound,
+                format!("Media with id {} not found in the store", id),
+            ));
+        }
+        p.insert(id, asset);
+        Ok(())
+    })
+}
+
+/// Returns the first live item whose galleries reference the media entry.
+fn referencing_item(id: MediaId) -> Option<ItemId> {
+    ITEMS_IN_ID.with_borrow(|ids| {
+        ITEMS.with_borrow(|items| {
+            ids.iter().find_map(|(item_id, key)| {
+                items
+                    .get(&key)
+                    .filter(|item| item.images().references_media(id))
+                    .map(|_| item_id)
+            })
+        })
+    })
+}
+
+/// Returns the first saved revision whose galleries reference the media entry.
+fn referencing_revision(id: MediaId) -> Option<ItemRevisionKey> {
+    ITEM_REVISIONS.with_borrow(|p| {
+        p.iter()
+            .find(|(_, entry)| entry.item.images().references_media(id))
+            .map(|(key, _)| key)
+    })
+}
+
+/// Returns the first review that attaches the media entry.
+fn referencing_review(id: MediaId) -> Option<ReviewKey> {
+    REVIEWS.with_borrow(|p| {
+        p.iter()
+            .find(|(_, review)| review.media_ids.contains(&id))
+            .map(|(key, _)| key)
+    })
+}
+
+/// Removes a media entry that no live item, saved revision or review references any more.
+pub(crate) fn remove_media(
+    caller: Principal,
+    id: MediaId,
+) -> Result<MediaAsset, (MediaErrorCode, String)> {
+    auth::require_staff::<MediaErrorCode>(caller)?;
+
+    if let Some(item_id) = referencing_item(id) {
+        return Err((
+            MediaErrorCode::MediaInUse,
+            format!(
+                "Media with id {} is still used by item with id {}",
+                id, item_id
+            ),
+        ));
+    }
+    if let Some(key) = referencing_revision(id) {
+        return Err((
+            MediaErrorCode::MediaInUse,
+            format!(
+                "Media with id {} is referenced by revision {} of item with id {}",
+                id, key.revision, key.item_id
+            ),
+        ));
+    }
+    if let Some(key) = referencing_review(id) {
+        return Err((
+            MediaErrorCode::MediaInUse,
+            format!(
+                "Media with id {} is attached to the review of item with id {} by {}",
+                id, key.item_id, key.author
+            ),
+        ));
+    }
+
+    MEDIA_LIBRARY.with_borrow_mut(|p| p.remove(&id)).ok_or((
+        MediaErrorCode::MediaNotFound,
+        format!("Media with id {} not found in the store", id),
+    ))
+}
+
+/// Returns the absolute URL an image entry links to. Paths of assets hosted by the canister
+/// are resolved against its certified domain, where they are served.
+pub(crate) fn media_url(media: &MediaDataWithCaption) -> Option<String> {
+    fn find_url(value: &serde_json::Value) -> Option<String> {
+        match value {
+            serde_json::Value::String(url)
+                if url.starts_with("https://") || url.starts_with("http://") =>
+            {
+                Some(url.clone())
+            }
+            serde_json::Value::String(path) if path.starts_with(asset::ASSET_PATH_PREFIX) => {
+                Some(format!("https://{}.icp0.io{}", ic_cdk::id(), path))
+            }
+            serde_json::Value::Array(values) => values.iter().find_map(find_url),
+            serde_json::Value::Object(fields) => fields.values().find_map(find_url),
+            _ => None,
+        }
+    }
+
+    find_url(&serde_json::to_value(media).ok()?)
+}
+
+/// Builds an image entry without a caption that links to `url`.
+pub(crate) fn media_from_url(url: &str) -> Result<MediaDataWithCaption, String> {
+    serde_json::from_value(serde_json::json!({ "url": url }))
+        .map_err(|e| format!("Invalid image URL {:?}: {}", url, e))
+}
+
+pub(crate) fn get_media(id: MediaId) -> Option<MediaAsset> {
+    MEDIA_LIBRARY.with_borrow(|p| p.get(&id))
+}
+
+pub(crate) fn contains_media(id: MediaId) -> bool {
+    MEDIA_LIBRARY.with_borrow(|p| p.contains_key(&id))
+}
+
+pub(crate) fn list_media(start: MediaId, limit: u32) -> Vec<(MediaId, MediaAsset)> {
+    MEDIA_LIBRARY.with_borrow(|p| p.range(start..).take(limit as usize).collect())
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenditionSize {
+    Thumbnail,
+    Card,
+    Zoom,
+    Original,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenditionFormat {
+    Original,
+    Webp,
+    Avif,
+}
+
+/// The renditions a client asks for: one size in each of the listed formats.
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct RenditionProfile {
+    pub size: RenditionSize,
+    pub formats: Vec<RenditionFormat>,
+}
+
+/// Store-wide configuration of the CDN that serves image renditions.
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct RenditionSettings {
+    // URL with `{id}`, `{width}` and `{format}` placeholders,
+    // e.g. `https://cdn.example.com/media/{id}/w{width}.{format}`
+    pub url_template: String,
+    pub thumbnail_width: u32,
+    pub card_width: u32,
+    pub zoom_width: u32,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct MediaRendition {
+    pub format: RenditionFormat,
+    pub mime_type: String,
+    // `None` for the original size
+    pub width: Option<u32>,
+    pub url: String,
+}
+
+impl RenditionSettings {
+    pub fn width(&self, size: RenditionSize) -> Option<u32> {
+        match size {
+            RenditionSize::Thumbnail => Some(self.thumbnail_width),
+            RenditionSize::Card => Some(self.card_width),
+            RenditionSize::Zoom => Some(self.zoom_width),
+            RenditionSize::Original => None,
+        }
+    }
+
+    /// Builds the URLs of a library media entry for every format of the profile.
+    pub fn renditions(
+        &self,
+        id: MediaId,
+        metadata: &MediaMetadata,
+        profile: &RenditionProfile,
+    ) -> Vec<MediaRendition> {
+        let width = self.width(profile.size);
+        let original_mime_type = metadata.mime_type.as_deref().unwrap_or("image/jpeg");
+
+        profile
+            .formats
+            .iter()
+            .map(|format| {
+                let mime_type = match format {
+                    RenditionFormat::Original => original_mime_type,
+                    RenditionFormat::Webp => "image/webp",
+                    RenditionFormat::Avif => "image/avif",
+                };
+                let extension = match mime_type.trim_start_matches("image/") {
+                    "jpeg" => "jpg",
+                    "svg+xml" => "svg",
+                    other => other,
+                };
+
+                let url = self
+                    .url_template
+                    .replace("{id}", &id.to_string())
+                    .replace(
+                        "{width}",
+                        &width.map_or("original".to_string(), |w| w.to_string()),
+                    )
+                    .replace("{format}", extension);
+
+                MediaRendition {
+                    format: *format,
+                    mime_type: mime_type.to_string(),
+                    width,
+                    url,
+                }
+            })
+            .collect()
+    }
+}
+
+pub(crate) fn update_rendition_settings(
+    caller: Principal,
+    settings: Option<RenditionSettings>,
+) -> Result<(), (AuthErrorCode, String)> {
+    auth::require_owner::<AuthErrorCode>(caller)?;
+    let _ = data::update_rendition_settings(settings);
+    Ok(())
+}
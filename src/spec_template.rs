@@ -0,0 +1,143 @@
+use crate::{
+    auth::{self, AuthErrorCode},
+    item::{filter, spec::SpecCategory},
+    ITEMS, ITEMS_IN_ID, ITEM_REVISIONS, SPEC_TEMPLATES,
+};
+use candid::{CandidType, Deserialize, Principal};
+
+/// Key of a spec category template in the store-level registry.
+pub type SpecTemplateId = u64;
+
+/// Largest number of live items whose index `update_spec_template` rebuilds in one call.
+pub const MAX_TEMPLATE_REINDEX_ITEMS: usize = 200;
+
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum SpecTemplateErrorCode {
+    Unauthorized,
+    TemplateNotFound,
+    TemplateInUse,
+    TooManyItems,
+}
+
+impl From<AuthErrorCode> for SpecTemplateErrorCode {
+    fn from(code: AuthErrorCode) -> Self {
+        match code {
+            AuthErrorCode::Unauthorized => SpecTemplateErrorCode::Unauthorized,
+        }
+    }
+}
+
+pub(crate) fn add_spec_template(
+    caller: Principal,
+    template: SpecCategory,
+) -> Result<SpecTemplateId, (SpecTemplateErrorCode, String)> {
+    auth::require_staff::<SpecTemplateErrorCode>(caller)?;
+
+    let id = SPEC_TEMPLATES.with_borrow_mut(|p| {
+        let id = p.last_key_value().map_or(0, |(id, _)| id + 1);
+        p.insert(id, template);
+        id
+    });
+
+    Ok(id)
+}
+
+/// Replaces a template. Every item that references it shows the new names right away,
+/// so labels and values should keep their keys when they are renamed.
+///
+/// The spec values of the referencing items are reindexed in the same call, so a template
+/// referenced by more than `MAX_TEMPLATE_REINDEX_ITEMS` live items is rejected;
+/// add a new template for those items instead.
+pub(crate) fn update_spec_template(
+    caller: Principal,
+    id: SpecTemplateId,
+    template: SpecCategory,
+) -> Result<(), (SpecTemplateErrorCode, String)> {
+    auth::require_staff::<SpecTemplateErrorCode>(caller)?;
+
+    if !SPEC_TEMPLATES.with_borrow(|p| p.contains_key(&id)) {
+        return Err((
+            SpecTemplateErrorCode::TemplateNotFound,
+            format!("Spec template with id {} not found in the store", id),
+        ));
+    }
+
+    // Indexed spec values of the referencing items change along with the template
+    let keys: Vec<_> = ITEMS_IN_ID.with_borrow(|p| p.iter().map(|(_, key)| key).collect());
+    let items: Vec<_> = ITEMS.with_borrow(|p| {
+        keys.iter()
+            .filter_map(|key| p.get(key))
+            .filter(|item| item.specs().template_ids().contains(&id))
+            .take(MAX_TEMPLATE_REINDEX_ITEMS + 1)
+            .collect()
+    });
+    if items.len() > MAX_TEMPLATE_REINDEX_ITEMS {
+        return Err((
+            SpecTemplateErrorCode::TooManyItems,
+            format!(
+                "Spec template with id {} is referenced by more than {} items",
+                id, MAX_TEMPLATE_REINDEX_ITEMS
+            ),
+        ));
+    }
+    items.iter().for_each(filter::unindex_item);
+    SPEC_TEMPLATES.with_borrow_mut(|p| p.insert(id, template));
+    items.iter().for_each(filter::index_item);
+
+    Ok(())
+}
+
+/// Removes a template that no item references, including stored revisions,
+/// which must stay renderable so that they can be rolled back to.
+pub(crate) fn remove_spec_template(
+    caller: Principal,
+    id: SpecTemplateId,
+) -> Result<SpecCategory, (SpecTemplateErrorCode, String)> {
+    auth::require_staff::<SpecTemplateErrorCode>(caller)?;
+
+    let in_use = ITEMS.with_borrow(|p| {
+        p.iter()
+            .find(|(_, item)| item.specs().template_ids().contains(&id))
+            .map(|(_, item)| item.id)
+    });
+    if let Some(item_id) = in_use {
+        return Err((
+            SpecTemplateErrorCode::TemplateInUse,
+            format!(
+                "Spec template with id {} is referenced by item with id {}",
+                id, item_id
+            ),
+        ));
+    }
+
+    let in_revision = ITEM_REVISIONS.with_borrow(|p| {
+        p.iter()
+            .find(|(_, entry)| entry.item.specs().template_ids().contains(&id))
+            .map(|(key, _)| key)
+    });
+    if let Some(key) = in_revision {
+        return Err((
+            SpecTemplateErrorCode::TemplateInUse,
+            format!(
+                "Spec template with id {} is referenced by revision {} of item with id {}",
+                id, key.revision, key.item_id
+            ),
+        ));
+    }
+
+    SPEC_TEMPLATES.with_borrow_mut(|p| p.remove(&id)).ok_or((
+        SpecTemplateErrorCode::TemplateNotFound,
+        format!("Spec template with id {} not found in the store", id),
+    ))
+}
+
+pub(crate) fn get_spec_template(id: SpecTemplateId) -> Option<SpecCategory> {
+    SPEC_TEMPLATES.with_borrow(|p| p.get(&id))
+}
+
+pub(crate) fn list_spec_templates(
+    start: SpecTemplateId,
+    limit: u32,
+) -> Vec<(SpecTemplateId, SpecCategory)> {
+    SPEC_TEMPLATES.with_borrow(|p| p.range(start..).take(limit as usize).collect())
+}
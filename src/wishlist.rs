@@ -0,0 +1,357 @@
+use crate::{
+    auth::{self, AuthErrorCode},
+    item::{attr::AttrKeysV2, get_item, Item},
+    WISHLISTS,
+};
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use common::{
+    item::{attr::Stock, ItemId},
+    unit::{Currency, Price},
+};
+use ic_stable_structures::{storable::Bound, Storable};
+use serde::Serialize;
+use std::borrow::Cow;
+
+pub const MAX_WISHLISTS_PER_PRINCIPAL: usize = 20;
+pub const MAX_WISHLIST_ENTRIES: usize = 200;
+pub const MAX_WISHLIST_NAME_LENGTH: usize = 100;
+
+/// Key of a wishlist. The wishlists of a principal are adjacent.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct WishlistKey {
+    pub owner: Principal,
+    pub name: String,
+}
+
+impl Storable for WishlistKey {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct WishlistEntry {
+    pub item_id: ItemId,
+    // `None` for the item as a whole, priced through its default variant
+    pub attr_keys: Option<AttrKeysV2>,
+    pub currency: Currency,
+    // `None` when the variant had no price in `currency`
+    pub price_when_added: Option<Price>,
+    pub was_in_stock: bool,
+    pub added_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Default)]
+pub struct Wishlist {
+    pub entries: Vec<WishlistEntry>,
+}
+
+impl Storable for Wishlist {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct WishlistEntryInput {
+    pub item_id: ItemId,
+    pub attr_keys: Option<AttrKeysV2>,
+    pub currency: Currency,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WishlistEntryState {
+    Available,
+    ItemRemoved,
+    VariantRemoved,
+}
+
+/// An entry of a wishlist with the current state of its item.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct WishlistEntryStatus {
+    pub entry: WishlistEntry,
+    pub state: WishlistEntryState,
+    pub current_price: Option<Price>,
+    // Current price less the price when added, negative when the price dropped
+    pub price_difference: Option<f64>,
+    pub price_dropped: bool,
+    pub stock: Option<Stock>,
+    // Out of stock when added, in stock now
+    pub restocked: bool,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum WishlistErrorCode {
+    Unauthorized,
+    ItemNotFound,
+    VariantNotFound,
+    WishlistNotFound,
+    EntryNotFound,
+    DuplicateEntry,
+    InvalidName,
+    TooManyWishlists,
+    TooManyEntries,
+}
+
+impl From<AuthErrorCode> for WishlistErrorCode {
+    fn from(code: AuthErrorCode) -> Self {
+        match code {
+            AuthErrorCode::Unauthorized => WishlistErrorCode::Unauthorized,
+        }
+    }
+}
+
+/// Resolves the variant an entry refers to: the given one, or the default one of the item.
+fn entry_keys(item: &Item, attr_keys: &Option<AttrKeysV2>) -> Option<AttrKeysV2> {
+    match attr_keys {
+        Some(attr_keys) => Some(attr_keys.normalized(item.dimensions())),
+        None => item.summary(None).attr_keys,
+    }
+}
+
+/// Current price and stock of the variant an entry refers to, as sold,
+/// so that entries of bundles follow their components.
+fn current_state(
+    item: &Item,
+    attr_keys: &Option<AttrKeysV2>,
+    currency: &Currency,
+) -> Option<(Option<Price>, Stock)> {
+    let attr_keys = entry_keys(item, attr_keys)?;
+    let data = item.get_variant(&attr_keys)?;
+    Some((
+        item.effective_price(data, currency),
+        item.effective_stock(data),
+    ))
+}
+
+/// Compares the keys of two entries of the same item at the given number of dimensions.
+fn same_keys(a: &Option<AttrKeysV2>, b: &Option<AttrKeysV2>, dimensions: Option<usize>) -> bool {
+    match (a, b, dimensions) {
+        (Some(a), Some(b), Some(dimensions)) => {
+            a.normalized(dimensions) == b.normalized(dimensions)
+        }
+        _ => a == b,
+    }
+}
+
+/// Trims a wishlist name and checks its length, so that every call addresses a wishlist
+/// by the name it was stored under.
+fn normalize_name(name: &str) -> Result<String, (WishlistErrorCode, String)> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_WISHLIST_NAME_LENGTH {
+        return Err((
+            WishlistErrorCode::InvalidName,
+            format!(
+                "Wishlist name must be between 1 and {} characters",
+                MAX_WISHLIST_NAME_LENGTH
+            ),
+        ));
+    }
+
+    Ok(name.to_string())
+}
+
+fn get_wishlist_of(key: &WishlistKey) -> Result<Wishlist, (WishlistErrorCode, String)> {
+    WISHLISTS.with_borrow(|p| p.get(key)).ok_or((
+        WishlistErrorCode::WishlistNotFound,
+        format!("Wishlist {} not found", key.name),
+    ))
+}
+
+fn wishlists_of(owner: Principal) -> Vec<(String, Wishlist)> {
+    let start = WishlistKey {
+        owner,
+        name: String::new(),
+    };
+
+    WISHLISTS.with_borrow(|p| {
+        p.range(start..)
+            .take_while(|(key, _)| key.owner == owner)
+            .map(|(key, wishlist)| (key.name, wishlist))
+            .collect()
+    })
+}
+
+/// Adds an entry to a wishlist of the caller, creating the wishlist if needed.
+/// The current price and stock of the variant are recorded for later comparison.
+pub(crate) fn add_to_wishlist(
+    caller: Principal,
+    name: String,
+    input: WishlistEntryInput,
+) -> Result<(), (WishlistErrorCode, String)> {
+    let owner = auth::require_authenticated::<WishlistErrorCode>(caller)?;
+    let name = normalize_name(&name)?;
+
+    let item = get_item(&input.item_id)
+        .map_err(|(_, message)| (WishlistErrorCode::ItemNotFound, message))?;
+    let attr_keys = input
+        .attr_keys
+        .map(|attr_keys| attr_keys.normalized(item.dimensions()));
+    let (price, stock) = current_state(&item, &attr_keys, &input.currency).ok_or((
+        WishlistErrorCode::VariantNotFound,
+        format!(
+            "Attribute with keys {:?} of item with id {} not found in the store",
+            attr_keys, input.item_id
+        ),
+    ))?;
+
+    let key = WishlistKey { owner, name };
+    let mut wishlist = match WISHLISTS.with_borrow(|p| p.get(&key)) {
+        Some(wishlist) => wishlist,
+        None if wishlists_of(owner).len() >= MAX_WISHLISTS_PER_PRINCIPAL => {
+            return Err((
+                WishlistErrorCode::TooManyWishlists,
+                format!(
+                    "A principal can have at most {} wishlists",
+                    MAX_WISHLISTS_PER_PRINCIPAL
+                ),
+            ))
+        }
+        None => Wishlist::default(),
+    };
+
+    if wishlist.entries.iter().any(|entry| {
+        entry.item_id == input.item_id
+            && same_keys(&entry.attr_keys, &attr_keys, Some(item.dimensions()))
+    }) {
+        return Err((
+            WishlistErrorCode::DuplicateEntry,
+            format!("Item with id {} is already in the wishlist", input.item_id),
+        ));
+    }
+    if wishlist.entries.len() >= MAX_WISHLIST_ENTRIES {
+        return Err((
+            WishlistErrorCode::TooManyEntries,
+            format!(
+                "A wishlist can have at most {} entries",
+                MAX_WISHLIST_ENTRIES
+            ),
+        ));
+    }
+
+    wishlist.entries.push(WishlistEntry {
+        item_id: input.item_id,
+        attr_keys,
+        currency: input.currency,
+        price_when_added: price,
+        was_in_stock: stock > 0,
+        added_at: ic_cdk::api::time(),
+    });
+    WISHLISTS.with_borrow_mut(|p| p.insert(key, wishlist));
+
+    Ok(())
+}
+
+pub(crate) fn remove_from_wishlist(
+    caller: Principal,
+    name: String,
+    item_id: ItemId,
+    attr_keys: Option<AttrKeysV2>,
+) -> Result<(), (WishlistErrorCode, String)> {
+    let owner = auth::require_authenticated::<WishlistErrorCode>(caller)?;
+
+    let key = WishlistKey {
+        owner,
+        name: normalize_name(&name)?,
+    };
+    let mut wishlist = get_wishlist_of(&key)?;
+    // Keys are compared at the current dimensions of the item, unless it no longer exists
+    let dimensions = get_item(&item_id).ok().map(|item| item.dimensions());
+    let position = wishlist
+        .entries
+        .iter()
+        .position(|entry| {
+            entry.item_id == item_id && same_keys(&entry.attr_keys, &attr_keys, dimensions)
+        })
+        .ok_or((
+            WishlistErrorCode::EntryNotFound,
+            format!("Item with id {} is not in the wishlist", item_id),
+        ))?;
+    wishlist.entries.remove(position);
+    WISHLISTS.with_borrow_mut(|p| p.insert(key, wishlist));
+
+    Ok(())
+}
+
+pub(crate) fn delete_wishlist(
+    caller: Principal,
+    name: String,
+) -> Result<(), (WishlistErrorCode, String)> {
+    let owner = auth::require_authenticated::<WishlistErrorCode>(caller)?;
+
+    let key = WishlistKey {
+        owner,
+        name: normalize_name(&name)?,
+    };
+    WISHLISTS.with_borrow_mut(|p| p.remove(&key)).ok_or((
+        WishlistErrorCode::WishlistNotFound,
+        format!("Wishlist {} not found", key.name),
+    ))?;
+
+    Ok(())
+}
+
+/// Lists the wishlists of the caller with their number of entries.
+pub(crate) fn list_wishlists(
+    caller: Principal,
+) -> Result<Vec<(String, u64)>, (WishlistErrorCode, String)> {
+    let owner = auth::require_authenticated::<WishlistErrorCode>(caller)?;
+
+    Ok(wishlists_of(owner)
+        .into_iter()
+        .map(|(name, wishlist)| (name, wishlist.entries.len() as u64))
+        .collect())
+}
+
+/// Returns the entries of a wishlist of the caller with the current price and stock of each,
+/// compared with those recorded when the entry was added.
+pub(crate) fn get_wishlist(
+    caller: Principal,
+    name: String,
+) -> Result<Vec<WishlistEntryStatus>, (WishlistErrorCode, String)> {
+    let owner = auth::require_authenticated::<WishlistErrorCode>(caller)?;
+    let wishlist = get_wishlist_of(&WishlistKey {
+        owner,
+        name: normalize_name(&name)?,
+    })?;
+
+    Ok(wishlist
+        .entries
+        .into_iter()
+        .map(|entry| {
+            let current = get_item(&entry.item_id)
+                .ok()
+                .map(|item| current_state(&item, &entry.attr_keys, &entry.currency));
+            let (state, price, stock) = match current {
+                None => (WishlistEntryState::ItemRemoved, None, None),
+                Some(None) => (WishlistEntryState::VariantRemoved, None, None),
+                Some(Some((price, stock))) => (WishlistEntryState::Available, price, Some(stock)),
+            };
+            let price_difference = price
+                .zip(entry.price_when_added)
+                .map(|(now, then)| now.value() - then.value());
+
+            WishlistEntryStatus {
+                state,
+                current_price: price,
+                price_difference,
+                price_dropped: price_difference.is_some_and(|difference| difference < 0.0),
+                stock,
+                restocked: !entry.was_in_stock && stock.is_some_and(|stock| stock > 0),
+                entry,
+            }
+        })
+        .collect())
+}